@@ -1,16 +1,16 @@
 //! # bibliograpy
 //!
 //! This module contains funtions related to bibliograpy and serialization and deserialization of .yml file.
+//! BibLaTeX `.bib` files are read as well, but never written.
 
 // #![allow(unused, dead_code, clippy::enum_variant_names)]
 #![allow(clippy::unwrap_used)]
 
-use std::env::current_dir;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 
 use anyhow::anyhow;
-use hayagriva::io::{from_yaml_str, to_yaml_str};
+use hayagriva::io::{from_biblatex_str, from_yaml_str, to_yaml_str};
 use hayagriva::types::EntryType;
 use hayagriva::{Entry, Library};
 use walkdir::{DirEntry, WalkDir};
@@ -18,16 +18,17 @@ use walkdir::{DirEntry, WalkDir};
 // use crate::typ_logger;
 
 fn is_hidden(entry: &DirEntry) -> bool {
-    entry
-        .file_name()
-        .to_str()
-        .map(|s| s.starts_with("."))
-        .unwrap_or(false)
+    entry.depth() > 0
+        && entry
+            .file_name()
+            .to_str()
+            .map(|s| s.starts_with("."))
+            .unwrap_or(false)
 }
 
-pub fn bibliography_file_path() -> anyhow::Result<PathBuf, anyhow::Error> {
-    let path = current_dir()?;
-    let walker = WalkDir::new(&path).into_iter();
+/// Finds the first `bibliography.yml` below `root`.
+pub fn bibliography_file_path(root: &Path) -> anyhow::Result<PathBuf, anyhow::Error> {
+    let walker = WalkDir::new(root).into_iter();
     for entry in walker.filter_entry(|e| !is_hidden(e)) {
         let val = Rc::new(entry?);
         let bib = val
@@ -44,15 +45,29 @@ pub fn bibliography_file_path() -> anyhow::Result<PathBuf, anyhow::Error> {
     Err(anyhow!("err"))
 }
 
-pub fn parse_bib() -> anyhow::Result<Library, anyhow::Error> {
-    let file = bibliography_file_path()?; 
+/// Whether a bibliography file is in the BibLaTeX format rather than Hayagriva's YAML.
+fn is_biblatex(file: &Path) -> bool {
+    file.extension().is_some_and(|ext| ext == "bib")
+}
+
+pub fn parse_bib(file: &Path) -> anyhow::Result<Library, anyhow::Error> {
     let content = std::fs::read_to_string(file)?;
     // Parse a bibliography
+    if is_biblatex(file) {
+        return from_biblatex_str(content.as_str()).map_err(|errors| {
+            let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+            anyhow!(
+                "invalid BibLaTeX in {}: {}",
+                file.display(),
+                errors.join(", ")
+            )
+        });
+    }
     Ok(from_yaml_str(content.as_str())?)
 }
 
-pub fn get_bib_keys() -> anyhow::Result<Vec<String>, anyhow::Error> {
-    let bib = parse_bib()?;
+pub fn get_bib_keys(file: &Path) -> anyhow::Result<Vec<String>, anyhow::Error> {
+    let bib = parse_bib(file)?;
     let mut vec = Vec::new();
 
     for item in bib.iter() {
@@ -62,11 +77,17 @@ pub fn get_bib_keys() -> anyhow::Result<Vec<String>, anyhow::Error> {
     Ok(vec)
 }
 
-pub fn new_bib_key(key: &str) -> anyhow::Result<bool, anyhow::Error> {
-    if let Ok(mut bib) = parse_bib() {
+pub fn new_bib_key(file: &Path, key: &str) -> anyhow::Result<bool, anyhow::Error> {
+    if is_biblatex(file) {
+        return Err(anyhow!(
+            "cannot add entries to BibLaTeX file {}",
+            file.display()
+        ));
+    }
+    if let Ok(mut bib) = parse_bib(file) {
         bib.push(&Entry::new(key, EntryType::Reference));
         let s = to_yaml_str(&bib)?;
-        std::fs::write(bibliography_file_path()?, s)?;
+        std::fs::write(file, s)?;
         return Ok(true);
     }
     Err(anyhow!("failed in bibliography funtion"))
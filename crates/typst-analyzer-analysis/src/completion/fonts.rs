@@ -1,11 +1,17 @@
-use fontdb::FaceInfo;
+use std::path::PathBuf;
+
+pub use fontdb::FaceInfo;
 
 use crate::{typ_logger, OkSome};
 
-pub fn get_fonts() -> OkSome<Vec<FaceInfo>> {
+/// The system fonts and the fonts in `font_paths`.
+pub fn get_fonts(font_paths: &[PathBuf]) -> OkSome<Vec<FaceInfo>> {
     let mut db = fontdb::Database::new();
     let mut fonts = Vec::new();
     db.load_system_fonts();
+    for dir in font_paths {
+        db.load_fonts_dir(dir);
+    }
     for font in db.faces() {
         fonts.push(font.to_owned());
     }
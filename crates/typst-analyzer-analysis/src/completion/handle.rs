@@ -3,11 +3,16 @@ use typst_syntax::{LinkedNode, SyntaxKind};
 
 use crate::typ_logger;
 
-use super::fonts::get_fonts;
 use super::{code, markup, snippets};
 
+use fontdb::FaceInfo;
+
+use crate::ProjectPaths;
+
+/// Completions for the nodes around the cursor, files are looked up in `project`.
 pub fn generate_completions(
     context: Vec<LinkedNode>,
+    project: &ProjectPaths,
 ) -> Result<Vec<CompletionItem>, anyhow::Error> {
     // Generate completion candidates based on the context
     let mut completions: Vec<CompletionItem> = vec![];
@@ -43,15 +48,15 @@ pub fn generate_completions(
 
         if node.kind() == SyntaxKind::BlockComment {
             completions.append(&mut collect_comments_cmp());
-            completions.append(&mut collect_fonts_cmp());
+            completions.append(&mut collect_fonts_cmp(&project.fonts));
         }
         if node.kind() == SyntaxKind::Bool {
             completions.append(&mut collect_comments_cmp());
         }
         if node.kind() == SyntaxKind::Markup {}
     }
-    completions.append(&mut snippets::collect());
-    if let Ok(mut markup_cmp) = markup::collect() {
+    completions.append(&mut snippets::collect(project.root.as_deref()));
+    if let Ok(mut markup_cmp) = markup::collect(&project.images) {
         completions.append(&mut markup_cmp)
     }
     completions.append(&mut code::collect());
@@ -94,20 +99,18 @@ fn collect_comments_cmp() -> Vec<CompletionItem> {
 ///   "Noto Sans Arabic",
 /// ))
 /// ```
-fn collect_fonts_cmp() -> Vec<CompletionItem> {
+fn collect_fonts_cmp(fonts: &[FaceInfo]) -> Vec<CompletionItem> {
     let mut items = Vec::new();
     // Add more specific completions based on the node kind
-    if let Ok(Some(comment_ctx)) = get_fonts() {
-        for label in comment_ctx {
-            let _insert_text = label.families;
-            items.push(CompletionItem {
-                label: "label".to_owned(),
-                kind: Some(CompletionItemKind::TEXT),
-                detail: Some("detail".to_owned()),
-                insert_text: Some("label".to_owned()),
-                ..Default::default()
-            });
-        }
+    for label in fonts {
+        let _insert_text = &label.families;
+        items.push(CompletionItem {
+            label: "label".to_owned(),
+            kind: Some(CompletionItemKind::TEXT),
+            detail: Some("detail".to_owned()),
+            insert_text: Some("label".to_owned()),
+            ..Default::default()
+        });
     }
     items
 }
@@ -28,20 +28,24 @@
 //!     Character escape Tweet at us \#ad Below
 //!     image
 
+#![allow(clippy::doc_overindented_list_items)]
+
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 
 use anyhow::Error;
 use tower_lsp::lsp_types::{CompletionItem, CompletionItemKind, InsertTextFormat, MarkupContent};
 
-use crate::{get_images, typ_logger};
+use crate::typ_logger;
 
 use super::core::TypCmpItem;
 
-pub fn collect() -> Result<Vec<CompletionItem>, Error> {
+/// Markup completions, with an `#image` for each of the images of the project.
+pub fn collect(images: &[PathBuf]) -> Result<Vec<CompletionItem>, Error> {
     let mut items = Vec::new();
     items.append(&mut collect_headers());
     items.append(&mut constructors());
-    items.append(&mut collect_image_cmp()?);
+    items.append(&mut collect_image_cmp(images));
     Ok(items)
 }
 
@@ -141,12 +145,13 @@ fn collect_headers() -> Vec<CompletionItem> {
     header_items
 }
 
-pub fn collect_image_cmp() -> Result<Vec<CompletionItem>, Error> {
+/// Images below the project root, relative to it. Paths starting with `/` are relative to the root
+/// in Typst, so the completions work in every file of the project.
+pub fn collect_image_cmp(images: &[PathBuf]) -> Vec<CompletionItem> {
     let mut items = Vec::new();
-    let images = get_images()?;
     typ_logger!("image: {:#?}", images);
     for item in images {
-        let image = item.to_string_lossy().to_string();
+        let image = Path::new("/").join(item).to_string_lossy().to_string();
         let item = TypCmpItem {
             label: "image".to_owned(),
             label_details: "markup".to_owned(),
@@ -157,5 +162,5 @@ pub fn collect_image_cmp() -> Result<Vec<CompletionItem>, Error> {
         typ_logger!("image: {}", image);
         items.push(item);
     }
-    Ok(TypCmpItem::convert(items))
+    TypCmpItem::convert(items)
 }
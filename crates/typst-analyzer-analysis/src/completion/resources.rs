@@ -1,11 +1,31 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use fontdb::FaceInfo;
 use walkdir::{DirEntry, WalkDir};
 
 use crate::typ_logger;
 
-pub fn get_images() -> Result<Vec<PathBuf>, anyhow::Error> {
+/// Files that mark the root of a Typst project.
+pub const PROJECT_MARKERS: [&str; 2] = ["typst.toml", "typst-analyzer.toml"];
+
+/// Paths a project looks things up in, and the files found there.
+///
+/// Images and fonts are found by the server, which keeps them until the files change.
+#[derive(Debug, Clone, Default)]
+pub struct ProjectPaths {
+    /// The project root, snippets are searched below it. Without a root this lookup is skipped.
+    pub root: Option<PathBuf>,
+    /// The images below the project root, relative to it, see [`get_images`].
+    pub images: Arc<Vec<PathBuf>>,
+    /// The system fonts and the fonts in the font paths of the project.
+    pub fonts: Arc<Vec<FaceInfo>>,
+}
+
+/// Returns the images below `root`, relative to it.
+pub fn get_images(root: &Path) -> Result<Vec<PathBuf>, anyhow::Error> {
     let mut images = Vec::new();
-    let c_dir = std::env::current_dir()?; // Get the current directory
+    let c_dir = root.to_path_buf();
     typ_logger!("c_dir: {:?}", c_dir);
 
     if !c_dir.exists() || !c_dir.is_dir() {
@@ -18,7 +38,7 @@ pub fn get_images() -> Result<Vec<PathBuf>, anyhow::Error> {
 
     let walker = WalkDir::new(&c_dir).into_iter(); // Initialize the iterator
 
-    for entry in walker.filter_entry(is_hidden) {
+    for entry in walker.filter_entry(|entry| !is_hidden(entry)) {
         match entry {
             Ok(entry) => {
                 typ_logger!("Found entry: {:?}", entry.path());
@@ -60,31 +80,62 @@ pub fn get_images() -> Result<Vec<PathBuf>, anyhow::Error> {
     Ok(images)
 }
 
-// Helper function to filter out hidden files
+// Helper function to filter out hidden files, the root itself is never skipped
 fn is_hidden(entry: &DirEntry) -> bool {
-    entry
-        .file_name()
-        .to_str()
-        .map(|s| s.starts_with("."))
-        .unwrap_or(false)
+    entry.depth() > 0
+        && entry
+            .file_name()
+            .to_str()
+            .map(|s| s.starts_with("."))
+            .unwrap_or(false)
 }
 
-/// Finds the project root by searching upward for a marker file/directory (e.g., `.git`)
-pub fn find_project_root() -> Option<PathBuf> {
-    let mut current_dir = std::env::current_dir().ok()?;
-    typ_logger!("Starting search for project root from: {:?}", current_dir);
-
-    while current_dir.parent().is_some() {
-        let marker = current_dir.join(".git"); // Change `.git` to another marker if needed
-        if marker.exists() {
-            typ_logger!("Found project root: {:?}", current_dir);
-            return Some(current_dir);
+/// Finds the project root by searching upward from `start` for one of the [`PROJECT_MARKERS`].
+///
+/// The search does not leave `boundary`, usually the workspace folder containing `start`.
+pub fn find_project_root(start: &Path, boundary: Option<&Path>) -> Option<PathBuf> {
+    typ_logger!("Starting search for project root from: {:?}", start);
+    for dir in start.ancestors() {
+        if PROJECT_MARKERS
+            .iter()
+            .any(|marker| dir.join(marker).is_file())
+        {
+            typ_logger!("Found project root: {:?}", dir);
+            return Some(dir.to_path_buf());
+        }
+        if Some(dir) == boundary {
+            break;
         }
-
-        // Move one directory up
-        current_dir = current_dir.parent()?.to_path_buf();
     }
-
     typ_logger!("No project root found");
     None
 }
+
+#[test]
+fn find_project_root_test() {
+    let tmp = std::env::temp_dir().join(format!("typst-analyzer-find-root-{}", std::process::id()));
+    let (outer, workspace) = (tmp.join("outer"), tmp.join("outer/workspace"));
+    let package = workspace.join("package");
+    for dir in [package.join("src/deep"), workspace.join("loose")] {
+        assert!(std::fs::create_dir_all(dir).is_ok());
+    }
+    assert!(std::fs::write(outer.join("typst.toml"), "").is_ok());
+    assert!(std::fs::write(package.join("typst-analyzer.toml"), "").is_ok());
+
+    // The closest marker wins
+    assert_eq!(
+        find_project_root(&package.join("src/deep"), Some(&workspace)),
+        Some(package.clone())
+    );
+    assert_eq!(find_project_root(&package, Some(&workspace)), Some(package));
+    // A marker above the boundary is not seen
+    assert_eq!(
+        find_project_root(&workspace.join("loose"), Some(&workspace)),
+        None
+    );
+    assert_eq!(
+        find_project_root(&workspace.join("loose"), None),
+        Some(outer)
+    );
+    let _ = std::fs::remove_dir_all(tmp);
+}
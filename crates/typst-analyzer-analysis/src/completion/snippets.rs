@@ -1,5 +1,5 @@
-use std::env::current_dir;
 use std::fs::{self, write};
+use std::path::Path;
 
 use dirs::config_dir;
 use serde::Deserialize;
@@ -10,10 +10,12 @@ use crate::typ_logger;
 use super::core::{ToTypCmpItem, TypCmpItem};
 
 /// Collect and provide all the snippets for the language server
-pub fn collect() -> Vec<CompletionItem> {
+///
+/// A `snippets.yml` in the project root, if there is one, replaces the global one.
+pub fn collect(root: Option<&Path>) -> Vec<CompletionItem> {
     let mut snippets = snips_time();
     snippets.append(&mut snips_set_items());
-    if let Some(snips) = snips_from_yaml().as_mut() {
+    if let Some(snips) = snips_from_yaml(root).as_mut() {
         snippets.append(snips)
     }
     TypCmpItem::convert(snippets.to_typ_cmp_item())
@@ -51,8 +53,8 @@ struct SnippetFile {
     snippets: Vec<UserSnippet>,
 }
 
-/// Load user defined snippets from the config directory or from the current directory
-fn load_user_snippets() -> Option<Vec<UserSnippet>> {
+/// Load user defined snippets from the config directory or from the project root
+fn load_user_snippets(root: Option<&Path>) -> Option<Vec<UserSnippet>> {
     // -- TODO: write this into config file using serde serialization
     let yml_ctx = "snippets:\n  - label: \"custom_date\"\n    details: \"Insert a custom date snippet\"\n    insert_text: \"#date(year: 2025, month: 1, day: 1)\"\n  - label: \"custom_time\"\n    details: \"Insert a custom time snippet\"\n    insert_text: \"#time(hour: 12, minute: 30, second: 45)\""
         .to_owned();
@@ -72,16 +74,10 @@ fn load_user_snippets() -> Option<Vec<UserSnippet>> {
                 Err(err) => typ_logger!("error: {}", err),
             }
         }
-        let snippets = if let Ok(c_dir) = current_dir() {
-            let local_snippets = c_dir.join("snippets.yml");
-            if local_snippets.exists() {
-                local_snippets
-            } else {
-                global_snippets
-            }
-        } else {
-            global_snippets
-        };
+        let snippets = root
+            .map(|root| root.join("snippets.yml"))
+            .filter(|local_snippets| local_snippets.exists())
+            .unwrap_or(global_snippets);
         if let Ok(content) = std::fs::read_to_string(snippets) {
             let snippet_file: Result<SnippetFile, serde_yml::Error> = serde_yml::from_str(&content);
             if let Ok(snippets) = snippet_file {
@@ -94,8 +90,8 @@ fn load_user_snippets() -> Option<Vec<UserSnippet>> {
 }
 
 /// serialize the user defined snippets in to SnippetMaker
-fn snips_from_yaml() -> Option<Vec<SnippetMaker>> {
-    load_user_snippets().map(|user_snippets| {
+fn snips_from_yaml(root: Option<&Path>) -> Option<Vec<SnippetMaker>> {
+    load_user_snippets(root).map(|user_snippets| {
         user_snippets
             .into_iter()
             .map(|snip| SnippetMaker {
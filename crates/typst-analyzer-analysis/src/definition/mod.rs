@@ -1 +1,400 @@
+//! # definition
+//!
+//! Scope-aware name resolution on the syntax tree.
+//!
+//! An identifier refers to the closest binding of its name that is visible at its position. Going
+//! up from the identifier, every enclosing scope is searched for bindings that come before it:
+//!
+//! - closure parameters and the name of a `let f(x) = ..` function are visible in its body,
+//! - the pattern of a `for` loop is visible in the loop body,
+//! - `let` bindings and imports are visible in the rest of the markup or code they appear in.
+//!
+//! Content and code blocks contain their own markup and code, so bindings made inside a block do
+//! not leak out of it, and a binding in an inner scope shadows the ones in outer scopes.
+//!
+//! Names imported from other files are resolved by the server, which reads the [`exports`] of
+//! the imported files.
 
+mod modules;
+
+use std::collections::HashMap;
+
+pub use modules::*;
+use typst_syntax::ast::{self, AstNode};
+use typst_syntax::{LinkedNode, Side, SyntaxKind, SyntaxNode};
+
+/// What introduced a binding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BindingKind {
+    /// `let x = 1` or a name in a destructuring pattern.
+    Variable,
+    /// `let f(x) = ..`.
+    Function,
+    /// A closure parameter.
+    Parameter,
+    /// The pattern of a `for` loop.
+    LoopVariable,
+    /// An item of `import "file.typ": item`.
+    Import,
+    /// The alias of `import "file.typ" as alias`.
+    ModuleAlias,
+    /// The module of `import "file.typ"`, named after the file.
+    Module,
+}
+
+/// A name bound in a file.
+#[derive(Debug, Clone)]
+pub struct Binding<'a> {
+    pub kind: BindingKind,
+    /// The identifier introducing the name.
+    pub ident: LinkedNode<'a>,
+    /// The node making the binding, like the `let` binding, closure, loop or import.
+    pub binder: LinkedNode<'a>,
+}
+
+impl Binding<'_> {
+    pub fn name(&self) -> &str {
+        match self.kind {
+            BindingKind::Module => module_name(self.ident.text()),
+            _ => self.ident.text(),
+        }
+    }
+}
+
+/// The identifier at the cursor, in code, in markup (`#title`) or in math.
+pub fn ident_at(root: &SyntaxNode, cursor: usize) -> Option<LinkedNode<'_>> {
+    let root = LinkedNode::new(root);
+    // At the end of an identifier the leaf after the cursor is whatever follows it
+    [Side::After, Side::Before]
+        .into_iter()
+        .filter_map(|side| root.leaf_at(cursor, side))
+        .find(|leaf| matches!(leaf.kind(), SyntaxKind::Ident | SyntaxKind::MathIdent))
+}
+
+/// Finds the binding an identifier refers to.
+///
+/// An identifier that makes a binding, like `x` in `let x = 1`, resolves to that binding, and an
+/// item of an import to the import. Returns `None` for names that are not bound in the file, like
+/// the functions of the standard library, and for fields and argument names, which are not looked
+/// up in the scope.
+///
+/// # Example
+/// ```
+/// use typst_analyzer_analysis::definition::{ident_at, resolve, BindingKind};
+///
+/// let text = "#let x = 1\n#let f(x) = x + 1\n#x";
+/// let root = typst_syntax::parse(text);
+/// // `x` in the body of `f` is the parameter, not the variable
+/// let in_body = ident_at(&root, text.find("+ 1").unwrap() - 2).unwrap();
+/// let binding = resolve(&in_body).unwrap();
+/// assert_eq!(binding.kind, BindingKind::Parameter);
+/// // `#x` at the end is the variable
+/// let in_markup = ident_at(&root, text.len()).unwrap();
+/// let binding = resolve(&in_markup).unwrap();
+/// assert_eq!((binding.kind, binding.ident.offset()), (BindingKind::Variable, 5));
+/// ```
+pub fn resolve<'a>(ident: &LinkedNode<'a>) -> Option<Binding<'a>> {
+    if field_target(ident).is_some() || is_argument_name(ident) {
+        return None;
+    }
+    if let Some(binding) = import_site(ident).or_else(|| declaration(ident)) {
+        return Some(binding);
+    }
+    lookup(ident, ident.text())
+}
+
+/// Finds the binding of a name that is visible at a node.
+pub fn lookup<'a>(node: &LinkedNode<'a>, name: &str) -> Option<Binding<'a>> {
+    lookup_with(node, name, |block, before, name| {
+        let bindings: Vec<Binding> = block
+            .children()
+            .take(before)
+            .flat_map(|sibling| bindings_of(&sibling))
+            .collect();
+        bindings.into_iter().rev().find(|b| b.name() == name)
+    })
+}
+
+/// Looks a name up in the scopes around a node, with `in_block` finding the last binding of the
+/// name among the first children of a markup or code block.
+fn lookup_with<'a>(
+    node: &LinkedNode<'a>,
+    name: &str,
+    mut in_block: impl FnMut(&LinkedNode<'a>, usize, &str) -> Option<Binding<'a>>,
+) -> Option<Binding<'a>> {
+    let mut child = node.clone();
+    while let Some(parent) = child.parent().cloned() {
+        let found = match parent.kind() {
+            SyntaxKind::Closure if is_body(&parent, &child) => closure_bindings(&parent),
+            SyntaxKind::ForLoop if is_body(&parent, &child) => loop_bindings(&parent),
+            SyntaxKind::Markup | SyntaxKind::Code => {
+                in_block(&parent, child.index(), name).into_iter().collect()
+            }
+            _ => Vec::new(),
+        };
+        // The last binding before the node shadows the earlier ones
+        if let Some(binding) = found.into_iter().rev().find(|b| b.name() == name) {
+            return Some(binding);
+        }
+        child = parent;
+    }
+    None
+}
+
+/// Resolves many identifiers of one tree like [`resolve`], but collects the bindings of every
+/// markup and code block only once. Resolving every identifier of a long document one by one
+/// searches the top level of the document again for each of them.
+#[derive(Debug, Default)]
+pub struct Scopes<'a> {
+    /// The bindings made in a block by name, with the index of the child making them, keyed by
+    /// the address of the block.
+    blocks: HashMap<usize, HashMap<String, Vec<(usize, Binding<'a>)>>>,
+}
+
+impl<'a> Scopes<'a> {
+    /// The binding an identifier refers to, see [`resolve`].
+    pub fn resolve(&mut self, ident: &LinkedNode<'a>) -> Option<Binding<'a>> {
+        if field_target(ident).is_some() || is_argument_name(ident) {
+            return None;
+        }
+        if let Some(binding) = import_site(ident).or_else(|| declaration(ident)) {
+            return Some(binding);
+        }
+        lookup_with(ident, ident.text(), |block, before, name| {
+            let key = block.get() as *const SyntaxNode as usize;
+            let names = self.blocks.entry(key).or_insert_with(|| {
+                let mut names: HashMap<String, Vec<(usize, Binding)>> = HashMap::new();
+                for child in block.children() {
+                    for binding in bindings_of(&child) {
+                        names
+                            .entry(binding.name().to_owned())
+                            .or_default()
+                            .push((child.index(), binding));
+                    }
+                }
+                names
+            });
+            let bindings = names.get(name)?;
+            let visible = bindings.partition_point(|(index, _)| *index < before);
+            bindings
+                .get(..visible)?
+                .last()
+                .map(|(_, binding)| binding.clone())
+        })
+    }
+}
+
+/// Whether renaming an identifier to `name` clashes with another binding, one that is visible at
+/// the identifier or one made next to it, like another parameter of the same function.
+pub fn conflicts(ident: &LinkedNode, name: &str) -> bool {
+    if lookup(ident, name).is_some() {
+        return true;
+    }
+    let Some(binding) = import_site(ident).or_else(|| declaration(ident)) else {
+        return false;
+    };
+    let binder = &binding.binder;
+    let siblings = match binder.kind() {
+        SyntaxKind::Closure => closure_bindings(binder),
+        SyntaxKind::ForLoop => loop_bindings(binder),
+        _ => bindings_of(binder),
+    };
+    siblings.iter().any(|sibling| sibling.name() == name)
+}
+
+/// The name of the function a named parameter belongs to, `f` for `width` in
+/// `let f(width: 1cm) = ..`. Only named parameters can be passed by name.
+pub fn parameter_function<'a>(binding: &Binding<'a>) -> Option<LinkedNode<'a>> {
+    if binding.kind != BindingKind::Parameter
+        || binding.ident.parent_kind() != Some(SyntaxKind::Named)
+    {
+        return None;
+    }
+    let name = binding.binder.cast::<ast::Closure>()?.name()?;
+    find_node(&binding.binder, name.to_untyped())
+}
+
+/// The names a `let` binding or an import makes visible in the rest of its scope.
+pub fn bindings_of<'a>(node: &LinkedNode<'a>) -> Vec<Binding<'a>> {
+    let binding = |kind, ident: ast::Ident| {
+        Some(Binding {
+            kind,
+            ident: find_node(node, ident.to_untyped())?,
+            binder: node.clone(),
+        })
+    };
+    if let Some(let_binding) = node.cast::<ast::LetBinding>() {
+        return match let_binding.kind() {
+            ast::LetBindingKind::Closure(name) => {
+                binding(BindingKind::Function, name).into_iter().collect()
+            }
+            ast::LetBindingKind::Normal(pattern) => pattern
+                .bindings()
+                .into_iter()
+                .filter_map(|ident| binding(BindingKind::Variable, ident))
+                .collect(),
+        };
+    }
+    if let Some(import) = node.cast::<ast::ModuleImport>() {
+        let mut bindings = Vec::new();
+        if let Some(ast::Imports::Items(items)) = import.imports() {
+            bindings.extend(
+                items
+                    .iter()
+                    .filter_map(|item| binding(BindingKind::Import, item.bound_name())),
+            );
+        }
+        match import.new_name() {
+            Some(alias) => bindings.extend(binding(BindingKind::ModuleAlias, alias)),
+            None if import.imports().is_none() => bindings.extend(
+                node.children()
+                    .find(|child| child.kind() == SyntaxKind::Str)
+                    .map(|source| Binding {
+                        kind: BindingKind::Module,
+                        ident: source,
+                        binder: node.clone(),
+                    }),
+            ),
+            None => {}
+        }
+        return bindings;
+    }
+    Vec::new()
+}
+
+/// The target of a field access whose field is `ident`, `module` in `module.item`.
+pub fn field_target<'a>(ident: &LinkedNode<'a>) -> Option<LinkedNode<'a>> {
+    let parent = ident.parent()?;
+    if parent.kind() != SyntaxKind::FieldAccess || ident.index() == 0 {
+        return None;
+    }
+    parent.children().next()
+}
+
+/// The binding an identifier makes, if it is the name in a `let` binding, a parameter or the
+/// pattern of a loop.
+fn declaration<'a>(ident: &LinkedNode<'a>) -> Option<Binding<'a>> {
+    let mut node = ident.parent().cloned();
+    while let Some(ancestor) = node {
+        let bindings = match ancestor.kind() {
+            SyntaxKind::LetBinding => bindings_of(&ancestor),
+            SyntaxKind::Closure => closure_bindings(&ancestor),
+            SyntaxKind::ForLoop => loop_bindings(&ancestor),
+            // Bindings do not reach over statements
+            SyntaxKind::Markup | SyntaxKind::Code => return None,
+            _ => Vec::new(),
+        };
+        if let Some(binding) = bindings
+            .into_iter()
+            .find(|binding| binding.ident.offset() == ident.offset())
+        {
+            return Some(binding);
+        }
+        node = ancestor.parent().cloned();
+    }
+    None
+}
+
+/// The binding made by an identifier of an import statement, an imported item or the alias.
+fn import_site<'a>(ident: &LinkedNode<'a>) -> Option<Binding<'a>> {
+    let mut node = ident.parent()?.clone();
+    let kind = match node.kind() {
+        SyntaxKind::ModuleImport if ident.prev_sibling_kind() == Some(SyntaxKind::As) => {
+            BindingKind::ModuleAlias
+        }
+        SyntaxKind::ModuleImport => return None,
+        _ => BindingKind::Import,
+    };
+    while matches!(
+        node.kind(),
+        SyntaxKind::ImportItemPath | SyntaxKind::RenamedImportItem | SyntaxKind::ImportItems
+    ) {
+        node = node.parent()?.clone();
+    }
+    (node.kind() == SyntaxKind::ModuleImport).then(|| Binding {
+        kind,
+        ident: ident.clone(),
+        binder: node,
+    })
+}
+
+/// Whether `ident` names an argument or a dictionary key, `x` in `f(x: 1)`.
+fn is_argument_name(ident: &LinkedNode) -> bool {
+    ident.parent_kind() == Some(SyntaxKind::Named)
+        && ident.index() == 0
+        && ident
+            .parent()
+            .and_then(|named| named.parent_kind())
+            .is_some_and(|kind| matches!(kind, SyntaxKind::Args | SyntaxKind::Dict))
+}
+
+/// Whether `child` is the body of a closure or loop, the last expression in it.
+fn is_body(parent: &LinkedNode, child: &LinkedNode) -> bool {
+    parent
+        .children()
+        .rev()
+        .find(|node| node.cast::<ast::Expr>().is_some())
+        .is_some_and(|body| body.index() == child.index())
+}
+
+/// The parameters of a closure and the name of a `let f(x) = ..` function, which is visible in
+/// its own body for recursion.
+fn closure_bindings<'a>(closure: &LinkedNode<'a>) -> Vec<Binding<'a>> {
+    let Some(ast_closure) = closure.cast::<ast::Closure>() else {
+        return Vec::new();
+    };
+    let binding = |kind, ident: ast::Ident| {
+        Some(Binding {
+            kind,
+            ident: find_node(closure, ident.to_untyped())?,
+            binder: closure.clone(),
+        })
+    };
+    let mut bindings: Vec<Binding> = ast_closure
+        .name()
+        .and_then(|name| binding(BindingKind::Function, name))
+        .into_iter()
+        .collect();
+    for param in ast_closure.params().children() {
+        let idents = match param {
+            ast::Param::Pos(pattern) => pattern.bindings(),
+            ast::Param::Named(named) => vec![named.name()],
+            ast::Param::Spread(spread) => spread.sink_ident().into_iter().collect(),
+        };
+        bindings.extend(
+            idents
+                .into_iter()
+                .filter_map(|ident| binding(BindingKind::Parameter, ident)),
+        );
+    }
+    bindings
+}
+
+fn loop_bindings<'a>(for_loop: &LinkedNode<'a>) -> Vec<Binding<'a>> {
+    let Some(ast_loop) = for_loop.cast::<ast::ForLoop>() else {
+        return Vec::new();
+    };
+    ast_loop
+        .pattern()
+        .bindings()
+        .into_iter()
+        .filter_map(|ident| {
+            Some(Binding {
+                kind: BindingKind::LoopVariable,
+                ident: find_node(for_loop, ident.to_untyped())?,
+                binder: for_loop.clone(),
+            })
+        })
+        .collect()
+}
+
+/// The linked node of a node in the subtree of `node`.
+///
+/// Unlike [`LinkedNode::find`] this does not rely on spans, which are all the same in trees that
+/// are parsed without a file.
+fn find_node<'a>(node: &LinkedNode<'a>, target: &SyntaxNode) -> Option<LinkedNode<'a>> {
+    if std::ptr::eq(node.get(), target) {
+        return Some(node.clone());
+    }
+    node.children().find_map(|child| find_node(&child, target))
+}
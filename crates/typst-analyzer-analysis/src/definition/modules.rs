@@ -0,0 +1,208 @@
+//! Modules and what they export.
+//!
+//! Every `let` binding and import at the top level of a file is part of its module, so
+//! `#import "template.typ": conf` finds `conf` among the top level bindings of `template.typ`.
+//! Names imported into a module are exported again, the server follows them file by file.
+
+use std::path::Path;
+
+use typst_syntax::ast::{self, AstNode};
+use typst_syntax::{LinkedNode, SyntaxKind, SyntaxNode};
+
+use super::{bindings_of, Binding, BindingKind};
+
+/// The bindings a file exports, in the order they are made.
+pub fn exports(root: &SyntaxNode) -> Vec<Binding<'_>> {
+    LinkedNode::new(root)
+        .children()
+        .flat_map(|child| bindings_of(&child))
+        .collect()
+}
+
+/// The `import "file.typ": *` statements at the top level of a file, the last one first.
+pub fn wildcard_imports(root: &SyntaxNode) -> Vec<LinkedNode<'_>> {
+    LinkedNode::new(root)
+        .children()
+        .rev()
+        .filter(is_wildcard_import)
+        .collect()
+}
+
+/// The `import "file.typ": *` statements in scope at an identifier, the closest one first.
+pub fn visible_wildcard_imports<'a>(ident: &LinkedNode<'a>) -> Vec<LinkedNode<'a>> {
+    let mut imports = Vec::new();
+    let mut child = ident.clone();
+    while let Some(parent) = child.parent().cloned() {
+        if matches!(parent.kind(), SyntaxKind::Markup | SyntaxKind::Code) {
+            imports.extend(
+                parent
+                    .children()
+                    .take(child.index())
+                    .rev()
+                    .filter(is_wildcard_import),
+            );
+        }
+        child = parent;
+    }
+    imports
+}
+
+fn is_wildcard_import(node: &LinkedNode) -> bool {
+    node.cast::<ast::ModuleImport>()
+        .is_some_and(|import| matches!(import.imports(), Some(ast::Imports::Wildcard)))
+}
+
+/// The path an import reads from as written, `template.typ` in `import "template.typ": conf`.
+///
+/// Imports from a module stored in a variable have no path.
+pub fn import_source(import: &LinkedNode) -> Option<String> {
+    match import.cast::<ast::ModuleImport>()?.source() {
+        ast::Expr::Str(source) => Some(source.get().as_str().to_owned()),
+        _ => None,
+    }
+}
+
+/// The names an imported item is found at in its module, `["a", "b"]` for `import "x.typ": a.b`
+/// and `import "x.typ": a.b as c`.
+pub fn imported_path(binding: &Binding) -> Vec<String> {
+    let Some(parent) = binding.ident.parent() else {
+        return Vec::new();
+    };
+    let path = match parent.kind() {
+        SyntaxKind::ImportItemPath => Some(parent.clone()),
+        SyntaxKind::RenamedImportItem => parent
+            .children()
+            .find(|child| child.kind() == SyntaxKind::ImportItemPath),
+        _ => None,
+    };
+    path.into_iter()
+        .flat_map(|path| path.children())
+        .filter(|child| child.kind() == SyntaxKind::Ident)
+        .map(|ident| ident.text().as_str().to_owned())
+        .collect()
+}
+
+/// The name `import "file.typ"` binds the module to, `file` for a file and the package name for
+/// a package.
+pub(crate) fn module_name(source: &str) -> &str {
+    let path = source.trim_matches('"');
+    match path.strip_prefix('@') {
+        // `@preview/cetz:0.3.0`
+        Some(spec) => spec.split(['/', ':']).nth(1).unwrap_or(spec),
+        None => Path::new(path)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or(path),
+    }
+}
+
+/// The code that makes a binding, like `let conf(title: none, body)` for a function.
+pub fn signature(binding: &Binding) -> String {
+    let text = |node: &LinkedNode| node.get().clone().into_text();
+    let binder = &binding.binder;
+    match binding.kind {
+        BindingKind::Function => {
+            let params = binder
+                .children()
+                .find(|child| child.kind() == SyntaxKind::Closure)
+                .and_then(|closure| {
+                    closure
+                        .children()
+                        .find(|child| child.kind() == SyntaxKind::Params)
+                })
+                .map(|params| text(&params))
+                .unwrap_or_default();
+            format!("let {}{}", binding.name(), params)
+        }
+        BindingKind::Variable => {
+            let Some(let_binding) = binder.cast::<ast::LetBinding>() else {
+                return text(binder).into();
+            };
+            let pattern = match let_binding.kind() {
+                ast::LetBindingKind::Normal(pattern) => pattern.to_untyped().clone().into_text(),
+                ast::LetBindingKind::Closure(name) => name.get().clone(),
+            };
+            match let_binding
+                .init()
+                .map(|init| init.to_untyped().clone().into_text())
+            {
+                // Long values do not tell much about the variable
+                Some(init) if init.len() <= 60 && !init.contains('\n') => {
+                    format!("let {pattern} = {init}")
+                }
+                _ => format!("let {pattern}"),
+            }
+        }
+        BindingKind::Parameter => {
+            let mut param = binding.ident.clone();
+            while let Some(parent) = param.parent().cloned() {
+                if parent.kind() == SyntaxKind::Params {
+                    break;
+                }
+                param = parent;
+            }
+            text(&param).into()
+        }
+        BindingKind::LoopVariable => {
+            let pattern = binder
+                .cast::<ast::ForLoop>()
+                .map(|for_loop| for_loop.pattern().to_untyped().clone().into_text())
+                .unwrap_or_default();
+            format!("for {pattern}")
+        }
+        BindingKind::Import | BindingKind::ModuleAlias | BindingKind::Module => text(binder).into(),
+    }
+}
+
+/// The line comments right above a `let` binding.
+pub fn docs(binding: &Binding) -> Option<String> {
+    if !matches!(binding.kind, BindingKind::Function | BindingKind::Variable) {
+        return None;
+    }
+    let binder = &binding.binder;
+    let mut lines = Vec::new();
+    for node in binder
+        .parent()?
+        .children()
+        .take(binder.index())
+        .rev()
+        // The `#` of a `#let` in markup
+        .skip_while(|node| node.kind() == SyntaxKind::Hash)
+    {
+        match node.kind() {
+            SyntaxKind::LineComment => {
+                let line = node.text().trim_start_matches('/');
+                lines.push(line.strip_prefix(' ').unwrap_or(line).to_owned());
+            }
+            // A blank line separates the comments from the binding
+            SyntaxKind::Space if node.text().matches('\n').count() <= 1 => {}
+            _ => break,
+        }
+    }
+    lines.reverse();
+    (!lines.is_empty()).then(|| lines.join("\n"))
+}
+
+#[test]
+fn exports_test() {
+    let root = typst_syntax::parse(
+        "#import \"base.typ\": a, b as c\n#import \"utils.typ\"\n\
+         // Sets up the document.\n#let conf(title: none, body) = body\n#{ let hidden = 1 }",
+    );
+    let exports = exports(&root);
+    let names: Vec<&str> = exports.iter().map(|binding| binding.name()).collect();
+    assert_eq!(names, ["a", "c", "utils", "conf"]);
+    assert_eq!(
+        exports.get(1).map(imported_path),
+        Some(vec!["b".to_owned()])
+    );
+    let conf = exports.get(3);
+    assert_eq!(
+        conf.map(signature).as_deref(),
+        Some("let conf(title: none, body)")
+    );
+    assert_eq!(
+        conf.and_then(docs).as_deref(),
+        Some("Sets up the document.")
+    );
+}
@@ -0,0 +1,217 @@
+//! # folding
+//!
+//! The parts of a document an editor can fold away.
+//!
+//! A heading folds its whole section, up to the next heading of the same or a higher level.
+//! Blocks, the arguments of calls, lists, raw blocks and equations on their own line fold as
+//! they are written. Consecutive line comments fold together, and `// region` and
+//! `// endregion` comments mark a region of their own.
+//!
+//! Ranges are byte ranges. Whether a range spans more than one line is up to the server, which
+//! knows the lines of the document.
+
+use std::ops::Range;
+
+use typst_syntax::ast;
+use typst_syntax::{LinkedNode, SyntaxKind, SyntaxNode};
+
+use crate::node::descendants;
+use crate::outline::{outline, OutlineItem, OutlineKind};
+
+/// What a folding range folds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FoldKind {
+    /// A heading and its section.
+    Section,
+    /// A `{ code }` or `[content]` block.
+    Block,
+    /// The arguments of a call, `(..)`.
+    Arguments,
+    /// Consecutive items of a bullet list, a numbered list or a term list.
+    List,
+    /// A block comment or consecutive line comments.
+    Comment,
+    /// A raw block between triple backticks.
+    Raw,
+    /// An equation on its own line, `$ x $`.
+    Equation,
+    /// The lines between `// region` and `// endregion`.
+    Region,
+}
+
+/// A part of a document that can be folded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fold {
+    pub kind: FoldKind,
+    pub range: Range<usize>,
+}
+
+/// The folding ranges of a document, sorted by where they start.
+///
+/// # Example
+/// ```
+/// use typst_analyzer_analysis::folding::{folding_ranges, FoldKind};
+///
+/// let text = "= Intro\nText.\n// region setup\n#let x = {\n  1\n}\n// endregion\n= Next";
+/// let root = typst_syntax::parse(text);
+/// let kinds: Vec<FoldKind> = folding_ranges(&root).iter().map(|fold| fold.kind).collect();
+/// assert_eq!(
+///     kinds,
+///     [FoldKind::Section, FoldKind::Region, FoldKind::Block, FoldKind::Section]
+/// );
+/// let section = &folding_ranges(&root)[0];
+/// assert_eq!(&text[section.range.clone()], &text[..text.find("\n= Next").unwrap_or(0)]);
+/// ```
+pub fn folding_ranges(root: &SyntaxNode) -> Vec<Fold> {
+    let text = root.clone().into_text();
+    let mut folds = Vec::new();
+    sections(&outline(root), &text, &mut folds);
+
+    let mut regions = Vec::new();
+    for node in descendants(root) {
+        let kind = match node.kind() {
+            SyntaxKind::CodeBlock | SyntaxKind::ContentBlock => Some(FoldKind::Block),
+            SyntaxKind::Args => Some(FoldKind::Arguments),
+            SyntaxKind::BlockComment => Some(FoldKind::Comment),
+            SyntaxKind::Raw if node.cast::<ast::Raw>().is_some_and(|raw| raw.block()) => {
+                Some(FoldKind::Raw)
+            }
+            SyntaxKind::Equation
+                if node
+                    .cast::<ast::Equation>()
+                    .is_some_and(|equation| equation.block()) =>
+            {
+                Some(FoldKind::Equation)
+            }
+            _ => None,
+        };
+        if let Some(kind) = kind {
+            folds.push(Fold {
+                kind,
+                range: node.range(),
+            });
+        }
+
+        match region_marker(&node) {
+            Some(true) => regions.push(node.range().start),
+            Some(false) => {
+                if let Some(start) = regions.pop() {
+                    folds.push(Fold {
+                        kind: FoldKind::Region,
+                        range: start..node.range().end,
+                    });
+                }
+            }
+            None => {}
+        }
+
+        runs(&node, &mut folds);
+    }
+    folds.sort_by_key(|fold| (fold.range.start, std::cmp::Reverse(fold.range.end)));
+    folds
+}
+
+/// The sections of headings, without the blank lines before the next heading.
+fn sections(items: &[OutlineItem], text: &str, folds: &mut Vec<Fold>) {
+    for item in items {
+        if !matches!(item.kind, OutlineKind::Heading(_)) {
+            continue;
+        }
+        let end = text
+            .get(item.range.clone())
+            .map_or(item.range.end, |section| {
+                item.range.start + section.trim_end().len()
+            });
+        folds.push(Fold {
+            kind: FoldKind::Section,
+            range: item.range.start..end,
+        });
+        sections(&item.children, text, folds);
+    }
+}
+
+/// Whether a node is a `// region` comment, `Some(true)`, or an `// endregion` comment,
+/// `Some(false)`.
+fn region_marker(node: &LinkedNode) -> Option<bool> {
+    if node.kind() != SyntaxKind::LineComment {
+        return None;
+    }
+    let comment = node.text().trim_start_matches('/').trim_start();
+    let word = comment.split_whitespace().next()?;
+    match word {
+        "region" => Some(true),
+        "endregion" => Some(false),
+        _ => None,
+    }
+}
+
+/// Folds runs of list items and of line comments among the children of a node.
+fn runs(node: &LinkedNode, folds: &mut Vec<Fold>) {
+    // The kind of the current run, where it starts and where its last node ends
+    let mut run: Option<(FoldKind, SyntaxKind, usize, usize)> = None;
+    for child in node.children() {
+        let kind = match child.kind() {
+            SyntaxKind::ListItem | SyntaxKind::EnumItem | SyntaxKind::TermItem => {
+                Some(FoldKind::List)
+            }
+            // Region markers are folds of their own
+            SyntaxKind::LineComment if region_marker(&child).is_none() => Some(FoldKind::Comment),
+            _ => None,
+        };
+        if let Some(kind) = kind {
+            match &mut run {
+                Some((_, syntax, _, end)) if *syntax == child.kind() => *end = child.range().end,
+                _ => {
+                    end_run(run.take(), folds);
+                    run = Some((kind, child.kind(), child.range().start, child.range().end));
+                }
+            }
+            continue;
+        }
+        // Items stay in one list across blank lines, comments only across line breaks
+        let continues = match (&run, child.kind()) {
+            (Some((FoldKind::List, ..)), SyntaxKind::Space | SyntaxKind::Parbreak) => true,
+            (Some((FoldKind::Comment, ..)), SyntaxKind::Space) => {
+                child.text().matches('\n').count() <= 1
+            }
+            _ => false,
+        };
+        if !continues {
+            end_run(run.take(), folds);
+        }
+    }
+    end_run(run, folds);
+}
+
+fn end_run(run: Option<(FoldKind, SyntaxKind, usize, usize)>, folds: &mut Vec<Fold>) {
+    if let Some((kind, _, start, end)) = run {
+        folds.push(Fold {
+            kind,
+            range: start..end,
+        });
+    }
+}
+
+#[test]
+fn folding_ranges_test() {
+    let text = "// a\n// b\n\n// c\n- one\n- two\n\n  more\n+ three\n```rust\nfn main() {}\n```\n\
+                $ x\n = y $\n#f(\n  1,\n)\n/* block */";
+    let root = typst_syntax::parse(text);
+    let folds: Vec<(FoldKind, &str)> = folding_ranges(&root)
+        .into_iter()
+        .map(|fold| (fold.kind, text.get(fold.range).unwrap_or_default()))
+        .collect();
+    assert_eq!(
+        folds,
+        [
+            (FoldKind::Comment, "// a\n// b"),
+            (FoldKind::Comment, "// c"),
+            (FoldKind::List, "- one\n- two\n\n  more"),
+            (FoldKind::List, "+ three"),
+            (FoldKind::Raw, "```rust\nfn main() {}\n```"),
+            (FoldKind::Equation, "$ x\n = y $"),
+            (FoldKind::Arguments, "(\n  1,\n)"),
+            (FoldKind::Comment, "/* block */"),
+        ]
+    );
+}
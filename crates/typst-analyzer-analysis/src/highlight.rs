@@ -0,0 +1,509 @@
+//! # highlight
+//!
+//! Semantic highlighting, the kind of every token as the parser sees it.
+//!
+//! Punctuation, operators, keywords and markup follow [`typst_syntax::highlight`], the
+//! highlighting of the Typst compiler. Identifiers are resolved to their bindings, so a function
+//! is told apart from a variable, a parameter or a module even where it is not called. Text in
+//! markup takes the kind of the element around it, a heading, strong or emphasized text or a
+//! link, with modifiers for the heading level and nested emphasis.
+
+use std::ops::Range;
+
+use typst_syntax::{ast, highlight as tag, LinkedNode, SyntaxKind, SyntaxNode, Tag};
+
+use crate::definition::{field_target, visible_wildcard_imports, BindingKind, Scopes};
+use crate::node::descendants;
+
+/// The kind of a token, in the order of the legend sent to the client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TokenType {
+    Comment,
+    String,
+    Number,
+    Keyword,
+    Operator,
+    Function,
+    Variable,
+    Parameter,
+    /// A module bound by an import.
+    Namespace,
+    /// A field or a key of a dictionary.
+    Property,
+    /// The language of a raw block, `rust` in ```` ```rust ````.
+    Decorator,
+    Punctuation,
+    /// An escape like `\#`, or a shorthand like `--`.
+    Escape,
+    Heading,
+    Strong,
+    Emph,
+    Link,
+    Raw,
+    Label,
+    Ref,
+    /// The marker of a list, a numbered list or a term list.
+    Marker,
+    /// The term of a term list.
+    Term,
+}
+
+impl TokenType {
+    /// Every token type, `token as usize` is the index in this list.
+    pub const LIST: &'static [TokenType] = &[
+        Self::Comment,
+        Self::String,
+        Self::Number,
+        Self::Keyword,
+        Self::Operator,
+        Self::Function,
+        Self::Variable,
+        Self::Parameter,
+        Self::Namespace,
+        Self::Property,
+        Self::Decorator,
+        Self::Punctuation,
+        Self::Escape,
+        Self::Heading,
+        Self::Strong,
+        Self::Emph,
+        Self::Link,
+        Self::Raw,
+        Self::Label,
+        Self::Ref,
+        Self::Marker,
+        Self::Term,
+    ];
+
+    /// The name in the legend, the ones defined by the protocol where there is one.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Comment => "comment",
+            Self::String => "string",
+            Self::Number => "number",
+            Self::Keyword => "keyword",
+            Self::Operator => "operator",
+            Self::Function => "function",
+            Self::Variable => "variable",
+            Self::Parameter => "parameter",
+            Self::Namespace => "namespace",
+            Self::Property => "property",
+            Self::Decorator => "decorator",
+            Self::Punctuation => "punctuation",
+            Self::Escape => "escape",
+            Self::Heading => "heading",
+            Self::Strong => "strong",
+            Self::Emph => "emph",
+            Self::Link => "link",
+            Self::Raw => "raw",
+            Self::Label => "label",
+            Self::Ref => "ref",
+            Self::Marker => "marker",
+            Self::Term => "term",
+        }
+    }
+}
+
+/// A modifier of a token, `1 << modifier as u32` is its bit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Modifier {
+    /// The identifier that makes a binding.
+    Declaration,
+    /// A name that is not bound in the file, like `text` or `calc`.
+    DefaultLibrary,
+    /// Inside an equation.
+    Math,
+    Strong,
+    Emph,
+    /// The level of a heading, headings below level 6 count as level 6.
+    Level1,
+    Level2,
+    Level3,
+    Level4,
+    Level5,
+    Level6,
+}
+
+impl Modifier {
+    /// Every modifier, `modifier as usize` is the index in this list.
+    pub const LIST: &'static [Modifier] = &[
+        Self::Declaration,
+        Self::DefaultLibrary,
+        Self::Math,
+        Self::Strong,
+        Self::Emph,
+        Self::Level1,
+        Self::Level2,
+        Self::Level3,
+        Self::Level4,
+        Self::Level5,
+        Self::Level6,
+    ];
+
+    /// The name in the legend.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Declaration => "declaration",
+            Self::DefaultLibrary => "defaultLibrary",
+            Self::Math => "math",
+            Self::Strong => "strong",
+            Self::Emph => "emph",
+            Self::Level1 => "level1",
+            Self::Level2 => "level2",
+            Self::Level3 => "level3",
+            Self::Level4 => "level4",
+            Self::Level5 => "level5",
+            Self::Level6 => "level6",
+        }
+    }
+
+    pub fn bit(self) -> u32 {
+        1 << self as u32
+    }
+
+    fn level(level: usize) -> Self {
+        match level {
+            0 | 1 => Self::Level1,
+            2 => Self::Level2,
+            3 => Self::Level3,
+            4 => Self::Level4,
+            5 => Self::Level5,
+            _ => Self::Level6,
+        }
+    }
+}
+
+/// A highlighted part of a document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub range: Range<usize>,
+    pub kind: TokenType,
+    /// The bits of the [`Modifier`]s.
+    pub modifiers: u32,
+}
+
+/// The tokens of a document, in document order and without overlaps.
+///
+/// A token may span more than one line, like a block comment.
+///
+/// # Example
+/// ```
+/// use typst_analyzer_analysis::highlight::{tokens, Modifier, TokenType};
+///
+/// let text = "#let f(x) = x\n== *Hi* #f(1)";
+/// let root = typst_syntax::parse(text);
+/// let tokens: Vec<(&str, TokenType, u32)> = tokens(&root)
+///     .into_iter()
+///     .map(|token| (&text[token.range], token.kind, token.modifiers))
+///     .collect();
+/// let declaration = Modifier::Declaration.bit();
+/// let level = Modifier::Level2.bit();
+/// assert_eq!(
+///     tokens,
+///     [
+///         ("#let", TokenType::Keyword, 0),
+///         ("f", TokenType::Function, declaration),
+///         ("(", TokenType::Punctuation, 0),
+///         ("x", TokenType::Parameter, declaration),
+///         (")", TokenType::Punctuation, 0),
+///         ("=", TokenType::Operator, 0),
+///         ("x", TokenType::Parameter, 0),
+///         ("==", TokenType::Heading, level),
+///         ("*", TokenType::Heading, level | Modifier::Strong.bit()),
+///         ("Hi", TokenType::Heading, level | Modifier::Strong.bit()),
+///         ("*", TokenType::Heading, level | Modifier::Strong.bit()),
+///         ("#f", TokenType::Function, 0),
+///         ("(", TokenType::Punctuation, 0),
+///         ("1", TokenType::Number, 0),
+///         (")", TokenType::Punctuation, 0),
+///     ]
+/// );
+/// ```
+pub fn tokens(root: &SyntaxNode) -> Vec<Token> {
+    let mut highlighter = Highlighter {
+        scopes: Scopes::default(),
+        wildcard_imports: descendants(root).any(|node| {
+            node.cast::<ast::ModuleImport>()
+                .is_some_and(|import| matches!(import.imports(), Some(ast::Imports::Wildcard)))
+        }),
+    };
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut hash: Option<usize> = None;
+    // Comments are leaves too, unlike with `LinkedNode::next_leaf`
+    for leaf in descendants(root).filter(|node| node.children().len() == 0 && !node.is_empty()) {
+        let Some((kind, modifiers)) = highlighter.classify(&leaf) else {
+            continue;
+        };
+        let range = leaf.range();
+        // `#` takes the kind of the expression after it, `#let` reads as one keyword
+        match tokens.last_mut() {
+            Some(last) if hash == Some(range.start) && last.kind == kind => {
+                last.range.end = range.end;
+                last.modifiers = modifiers & !Modifier::Declaration.bit();
+            }
+            _ => tokens.push(Token {
+                range: range.clone(),
+                kind,
+                modifiers,
+            }),
+        }
+        hash = (leaf.kind() == SyntaxKind::Hash).then_some(range.end);
+    }
+    tokens
+}
+
+struct Highlighter<'a> {
+    scopes: Scopes<'a>,
+    /// Whether the document has an `import "file.typ": *` anywhere.
+    wildcard_imports: bool,
+}
+
+impl<'a> Highlighter<'a> {
+    /// The kind and modifiers of a leaf.
+    fn classify(&mut self, leaf: &LinkedNode<'a>) -> Option<(TokenType, u32)> {
+        let context = Context::of(leaf);
+        let kind = match leaf.kind() {
+            SyntaxKind::Ident | SyntaxKind::MathIdent => return self.ident(leaf, &context),
+            SyntaxKind::Hash => {
+                let expr = leaf.next_sibling()?.leftmost_leaf()?;
+                return self.classify(&expr);
+            }
+            SyntaxKind::RawLang => TokenType::Decorator,
+            SyntaxKind::RawDelim => TokenType::Raw,
+            SyntaxKind::Text if leaf.parent_kind() == Some(SyntaxKind::Raw) => TokenType::Raw,
+            // Single letters, digits and symbols in math are text
+            SyntaxKind::Text
+                if context.kind.is_none() && context.modifiers & Modifier::Math.bit() != 0 =>
+            {
+                math_text(leaf.text())
+            }
+            SyntaxKind::Space | SyntaxKind::Parbreak | SyntaxKind::RawTrimmed => return None,
+            _ => match tag(leaf) {
+                Some(tag) => token_type(tag)?,
+                None => context.kind?,
+            },
+        };
+        let modifiers = match kind {
+            // Markup keeps its emphasis, code in it does not
+            TokenType::Heading
+            | TokenType::Strong
+            | TokenType::Emph
+            | TokenType::Link
+            | TokenType::Term
+            | TokenType::Ref
+            | TokenType::Label => context.modifiers,
+            _ => context.modifiers & Modifier::Math.bit(),
+        };
+        Some((kind, modifiers))
+    }
+
+    /// An identifier, by what it is bound to.
+    fn ident(&mut self, ident: &LinkedNode<'a>, context: &Context) -> Option<(TokenType, u32)> {
+        let math = context.modifiers & Modifier::Math.bit();
+        let called = tag(ident) == Some(Tag::Function);
+        if let Some(binding) = self.scopes.resolve(ident) {
+            let kind = match binding.kind {
+                _ if called => TokenType::Function,
+                BindingKind::Function => TokenType::Function,
+                BindingKind::Parameter => TokenType::Parameter,
+                BindingKind::Variable | BindingKind::LoopVariable | BindingKind::Import => {
+                    TokenType::Variable
+                }
+                BindingKind::ModuleAlias | BindingKind::Module => TokenType::Namespace,
+            };
+            let declaration = match binding.ident.range() == ident.range() {
+                true => Modifier::Declaration.bit(),
+                false => 0,
+            };
+            return Some((kind, math | declaration));
+        }
+        // `module.item`
+        if field_target(ident).is_some() {
+            let kind = match called {
+                true => TokenType::Function,
+                false => TokenType::Property,
+            };
+            return Some((kind, math));
+        }
+        // The name of an argument, `columns` in `table(columns: 2)`, or the key of a dictionary
+        if ident.parent_kind() == Some(SyntaxKind::Named) && ident.index() == 0 {
+            let kind = match ident.parent().and_then(|named| named.parent_kind()) {
+                Some(SyntaxKind::Args) => TokenType::Parameter,
+                _ => TokenType::Property,
+            };
+            return Some((kind, math));
+        }
+        let kind = match called {
+            true => TokenType::Function,
+            false => TokenType::Variable,
+        };
+        // Names from `import "file.typ": *` are not part of the library
+        let library = match !self.wildcard_imports || visible_wildcard_imports(ident).is_empty() {
+            true => Modifier::DefaultLibrary.bit(),
+            false => 0,
+        };
+        Some((kind, math | library))
+    }
+}
+
+fn math_text(text: &str) -> TokenType {
+    if text.chars().all(|c| c.is_ascii_digit() || c == '.') {
+        TokenType::Number
+    } else if text.chars().all(char::is_alphabetic) {
+        TokenType::Variable
+    } else {
+        TokenType::Operator
+    }
+}
+
+fn token_type(tag: Tag) -> Option<TokenType> {
+    Some(match tag {
+        Tag::Comment => TokenType::Comment,
+        Tag::Punctuation | Tag::MathDelimiter => TokenType::Punctuation,
+        Tag::Escape => TokenType::Escape,
+        Tag::Strong => TokenType::Strong,
+        Tag::Emph => TokenType::Emph,
+        Tag::Link => TokenType::Link,
+        Tag::Raw => TokenType::Raw,
+        Tag::Label => TokenType::Label,
+        Tag::Ref => TokenType::Ref,
+        Tag::Heading => TokenType::Heading,
+        Tag::ListMarker => TokenType::Marker,
+        Tag::ListTerm => TokenType::Term,
+        Tag::MathOperator | Tag::Operator => TokenType::Operator,
+        Tag::Keyword => TokenType::Keyword,
+        Tag::Number => TokenType::Number,
+        Tag::String => TokenType::String,
+        Tag::Function => TokenType::Function,
+        Tag::Interpolated => TokenType::Variable,
+        // Errors are reported as diagnostics
+        Tag::Error => return None,
+    })
+}
+
+/// The markup a leaf is in, up to the closest code.
+#[derive(Debug, Default)]
+struct Context {
+    /// The kind of text in the innermost element.
+    kind: Option<TokenType>,
+    modifiers: u32,
+}
+
+impl Context {
+    fn of(leaf: &LinkedNode) -> Self {
+        let mut context = Context::default();
+        let mut node = leaf.parent().cloned();
+        while let Some(parent) = node {
+            match parent.kind() {
+                SyntaxKind::Heading => {
+                    let level = parent
+                        .children()
+                        .find(|child| child.kind() == SyntaxKind::HeadingMarker)
+                        .map_or(1, |marker| marker.len());
+                    context.modifiers |= Modifier::level(level).bit();
+                    context.kind.get_or_insert(TokenType::Heading);
+                }
+                SyntaxKind::Strong => {
+                    context.modifiers |= Modifier::Strong.bit();
+                }
+                SyntaxKind::Emph => {
+                    context.modifiers |= Modifier::Emph.bit();
+                }
+                SyntaxKind::Equation => {
+                    context.modifiers |= Modifier::Math.bit();
+                }
+                SyntaxKind::Markup | SyntaxKind::TermItem | SyntaxKind::Ref => {
+                    if let Some(tag) = tag(&parent).and_then(token_type) {
+                        context.kind.get_or_insert(tag);
+                    }
+                }
+                SyntaxKind::Math
+                | SyntaxKind::MathDelimited
+                | SyntaxKind::MathAttach
+                | SyntaxKind::MathFrac
+                | SyntaxKind::MathRoot
+                | SyntaxKind::MathPrimes
+                | SyntaxKind::ListItem
+                | SyntaxKind::EnumItem => {}
+                // Code and content in it is highlighted on its own
+                _ => break,
+            }
+            node = parent.parent().cloned();
+        }
+        // Strong or emphasized text outside of a heading
+        if context.kind.is_none() {
+            if context.modifiers & Modifier::Strong.bit() != 0 {
+                context.kind = Some(TokenType::Strong);
+            } else if context.modifiers & Modifier::Emph.bit() != 0 {
+                context.kind = Some(TokenType::Emph);
+            }
+        }
+        context
+    }
+}
+
+#[test]
+fn tokens_test() {
+    let text =
+        "#import \"a.typ\" as m\n#let conf(body, title: none) = {\n  for i in body { m.f(i) }\n}\n\
+                - _Hi_ \\# $alpha + x$ @intro <intro>\n```rust\nfn main() {}\n```\n\
+                #table(columns: 2, text(red)[x]) /* note */";
+    let root = typst_syntax::parse(text);
+    let tokens: Vec<(&str, TokenType, u32)> = tokens(&root)
+        .into_iter()
+        .filter(|token| token.kind != TokenType::Punctuation)
+        .map(|token| {
+            (
+                text.get(token.range).unwrap_or_default(),
+                token.kind,
+                token.modifiers,
+            )
+        })
+        .collect();
+    let declaration = Modifier::Declaration.bit();
+    let math = Modifier::Math.bit();
+    let library = Modifier::DefaultLibrary.bit();
+    let emph = Modifier::Emph.bit();
+    assert_eq!(
+        tokens,
+        [
+            ("#import", TokenType::Keyword, 0),
+            ("\"a.typ\"", TokenType::String, 0),
+            ("as", TokenType::Keyword, 0),
+            ("m", TokenType::Namespace, declaration),
+            ("#let", TokenType::Keyword, 0),
+            ("conf", TokenType::Function, declaration),
+            ("body", TokenType::Parameter, declaration),
+            ("title", TokenType::Parameter, declaration),
+            ("none", TokenType::Keyword, 0),
+            ("=", TokenType::Operator, 0),
+            ("for", TokenType::Keyword, 0),
+            ("i", TokenType::Variable, declaration),
+            ("in", TokenType::Keyword, 0),
+            ("body", TokenType::Parameter, 0),
+            ("m", TokenType::Namespace, 0),
+            ("f", TokenType::Function, 0),
+            ("i", TokenType::Variable, 0),
+            ("-", TokenType::Marker, 0),
+            ("_", TokenType::Emph, emph),
+            ("Hi", TokenType::Emph, emph),
+            ("_", TokenType::Emph, emph),
+            ("\\#", TokenType::Escape, 0),
+            ("alpha", TokenType::Variable, math | library),
+            ("+", TokenType::Operator, math),
+            ("x", TokenType::Variable, math),
+            ("@intro", TokenType::Ref, 0),
+            ("<intro>", TokenType::Label, 0),
+            ("```", TokenType::Raw, 0),
+            ("rust", TokenType::Decorator, 0),
+            ("fn main() {}", TokenType::Raw, 0),
+            ("```", TokenType::Raw, 0),
+            ("#table", TokenType::Function, library),
+            ("columns", TokenType::Parameter, 0),
+            ("2", TokenType::Number, 0),
+            ("text", TokenType::Function, library),
+            ("red", TokenType::Variable, library),
+            ("/* note */", TokenType::Comment, 0),
+        ]
+    );
+}
@@ -4,8 +4,12 @@ pub mod completion;
 pub mod definition;
 pub mod dict;
 pub mod error;
+pub mod folding;
+pub mod highlight;
 mod hints;
 pub mod node;
+pub mod outline;
+pub mod selection;
 
 pub use completion::resources::*;
 pub use hints::handle::*;
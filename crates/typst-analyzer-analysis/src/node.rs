@@ -25,7 +25,7 @@ pub fn kind_walker(cursor: usize, ast: &SyntaxNode) -> VecDeque<SyntaxKind> {
 
 /// Walks down the AST from current cursor position and Returns a VecDeque of SyntaxKind.
 /// Must provide markup in vector in all cases since thas is the root.
-pub fn node_walker(cursor: usize, ast: &SyntaxNode) -> VecDeque<LinkedNode> {
+pub fn node_walker(cursor: usize, ast: &SyntaxNode) -> VecDeque<LinkedNode<'_>> {
     let linked_root = LinkedNode::new(ast);
     // Find the LinkedNode at the cursor position
     let current_node = linked_root.leaf_at(cursor, typst_syntax::Side::Before);
@@ -43,3 +43,44 @@ pub fn node_walker(cursor: usize, ast: &SyntaxNode) -> VecDeque<LinkedNode> {
     }
     nodes
 }
+
+/// Walks the whole syntax tree below `root` in document order, visiting parents before their
+/// children.
+///
+/// Labels, references and most other things we are interested in can be nested anywhere: in
+/// headings, list items, content blocks, figure captions or function arguments. Iterating only
+/// the children of the root misses all of them.
+///
+/// # Example
+/// ```
+/// use typst_analyzer_analysis::node::descendants;
+/// use typst_syntax::SyntaxKind;
+///
+/// let root = typst_syntax::parse("= Intro <intro>\n- see @intro\n#figure(caption: [@intro])");
+/// let refs = descendants(&root)
+///     .filter(|node| node.kind() == SyntaxKind::Ref)
+///     .count();
+/// assert_eq!(refs, 2);
+/// ```
+pub fn descendants(root: &SyntaxNode) -> Descendants<'_> {
+    Descendants {
+        stack: vec![LinkedNode::new(root)],
+    }
+}
+
+/// Iterator returned by [`descendants`].
+#[derive(Debug, Clone)]
+pub struct Descendants<'a> {
+    stack: Vec<LinkedNode<'a>>,
+}
+
+impl<'a> Iterator for Descendants<'a> {
+    type Item = LinkedNode<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        // Children are pushed in reverse so the first child is visited next
+        self.stack.extend(node.children().rev());
+        Some(node)
+    }
+}
@@ -0,0 +1,246 @@
+//! # outline
+//!
+//! The structure of a document as shown in the outline of an editor.
+//!
+//! Headings nest by their level, and everything else, figures, tables, block equations, labels,
+//! `let` bindings and `show`/`set` rules, is put under the section it appears in. A section
+//! reaches from its heading to the next heading of the same or a higher level.
+
+use std::ops::Range;
+
+use typst_syntax::ast::{self, AstNode};
+use typst_syntax::{LinkedNode, SyntaxKind, SyntaxNode};
+
+use crate::definition::{bindings_of, signature, BindingKind};
+use crate::node::descendants;
+
+/// What an outline entry is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OutlineKind {
+    /// A heading with its level, starting at 1.
+    Heading(usize),
+    Figure,
+    Table,
+    /// An equation on its own line, `$ x $`.
+    Equation,
+    Label,
+    Function,
+    Variable,
+    ShowRule,
+    SetRule,
+}
+
+/// An entry of the outline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutlineItem {
+    pub name: String,
+    pub detail: Option<String>,
+    pub kind: OutlineKind,
+    /// The whole entry, for a heading the whole section.
+    pub range: Range<usize>,
+    /// The part naming the entry, like the heading or the name of a binding.
+    pub selection: Range<usize>,
+    pub children: Vec<OutlineItem>,
+}
+
+/// The outline of a document.
+///
+/// # Example
+/// ```
+/// use typst_analyzer_analysis::outline::{outline, OutlineKind};
+///
+/// let root = typst_syntax::parse("= Intro\n#let x = 1\n== Details <details>\n= Next");
+/// let outline = outline(&root);
+/// let names: Vec<&str> = outline.iter().map(|item| item.name.as_str()).collect();
+/// assert_eq!(names, ["Intro", "Next"]);
+/// let intro: Vec<OutlineKind> = outline[0].children.iter().map(|item| item.kind).collect();
+/// assert_eq!(intro, [OutlineKind::Variable, OutlineKind::Heading(2)]);
+/// assert_eq!(outline[0].children[1].children[0].name, "details");
+/// ```
+pub fn outline(root: &SyntaxNode) -> Vec<OutlineItem> {
+    let mut items = Vec::new();
+    collect(&LinkedNode::new(root), &mut items);
+    nest(items, root.len())
+}
+
+/// Collects the entries below a node in document order, without nesting them.
+fn collect(node: &LinkedNode, items: &mut Vec<OutlineItem>) {
+    let before = items.len();
+    match node.kind() {
+        SyntaxKind::Heading => items.extend(heading(node)),
+        SyntaxKind::Equation
+            if node
+                .cast::<ast::Equation>()
+                .is_some_and(|equation| equation.block()) =>
+        {
+            items.push(item(text(node), None, OutlineKind::Equation, node, node));
+        }
+        SyntaxKind::Label if node.parent_kind() == Some(SyntaxKind::Markup) => {
+            if let Some(label) = node.cast::<ast::Label>() {
+                let name = label.get().to_owned();
+                items.push(item(name, None, OutlineKind::Label, node, node));
+            }
+        }
+        SyntaxKind::LetBinding => {
+            for binding in bindings_of(node) {
+                let kind = match binding.kind {
+                    BindingKind::Function => OutlineKind::Function,
+                    _ => OutlineKind::Variable,
+                };
+                let detail = Some(signature(&binding));
+                items.push(item(
+                    binding.name().to_owned(),
+                    detail,
+                    kind,
+                    node,
+                    &binding.ident,
+                ));
+            }
+        }
+        SyntaxKind::ShowRule | SyntaxKind::SetRule => {
+            let kind = match node.kind() {
+                SyntaxKind::ShowRule => OutlineKind::ShowRule,
+                _ => OutlineKind::SetRule,
+            };
+            items.push(item(text(node), None, kind, node, node));
+        }
+        SyntaxKind::FuncCall => items.extend(figure(node)),
+        _ => {}
+    }
+    // Entries do not contain other entries, but the arguments of other calls can
+    if items.len() == before {
+        for child in node.children() {
+            collect(&child, items);
+        }
+    }
+}
+
+fn heading(node: &LinkedNode) -> Option<OutlineItem> {
+    let heading = node.cast::<ast::Heading>()?;
+    let level = heading.depth().get();
+    Some(item(
+        plain_text(heading.body().to_untyped()),
+        None,
+        OutlineKind::Heading(level),
+        node,
+        node,
+    ))
+}
+
+/// A `figure` or a `table` call, named after the caption of a figure.
+fn figure(node: &LinkedNode) -> Option<OutlineItem> {
+    let call = node.cast::<ast::FuncCall>()?;
+    let ast::Expr::Ident(callee) = call.callee() else {
+        return None;
+    };
+    let kind = match callee.as_str() {
+        "figure" => OutlineKind::Figure,
+        "table" => OutlineKind::Table,
+        _ => return None,
+    };
+    let caption = call.args().items().find_map(|arg| match arg {
+        ast::Arg::Named(named) if named.name().as_str() == "caption" => {
+            Some(named.expr().to_untyped().clone())
+        }
+        _ => None,
+    });
+    let name = match caption {
+        Some(caption) => plain_text(&caption),
+        None => callee.as_str().to_owned(),
+    };
+    let callee = node.children().next().unwrap_or_else(|| node.clone());
+    Some(item(name, None, kind, node, &callee))
+}
+
+fn item(
+    name: String,
+    detail: Option<String>,
+    kind: OutlineKind,
+    node: &LinkedNode,
+    selection: &LinkedNode,
+) -> OutlineItem {
+    OutlineItem {
+        // Clients reject entries without a name, like an empty heading
+        name: match name.is_empty() {
+            true => text(node),
+            false => name,
+        },
+        detail,
+        kind,
+        range: node.range(),
+        selection: selection.range(),
+        children: Vec::new(),
+    }
+}
+
+/// The code of a node on one line.
+fn text(node: &LinkedNode) -> String {
+    node.get()
+        .clone()
+        .into_text()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The text of content on one line, without markup like `*strong*`.
+fn plain_text(node: &SyntaxNode) -> String {
+    let mut text = String::new();
+    for leaf in descendants(node) {
+        match leaf.kind() {
+            SyntaxKind::Text | SyntaxKind::Escape | SyntaxKind::Shorthand => {
+                text.push_str(leaf.text())
+            }
+            SyntaxKind::Space if !text.ends_with(' ') => text.push(' '),
+            _ => {}
+        }
+    }
+    text.trim().to_owned()
+}
+
+/// Puts every entry under the closest heading before it with a lower level, and stretches the
+/// range of headings over their section.
+fn nest(items: Vec<OutlineItem>, end: usize) -> Vec<OutlineItem> {
+    let mut roots = Vec::new();
+    // The open sections, from the outermost to the innermost
+    let mut sections: Vec<OutlineItem> = Vec::new();
+    for item in items {
+        if let OutlineKind::Heading(level) = item.kind {
+            while sections
+                .last()
+                .is_some_and(|section| section_level(section) >= level)
+            {
+                close_section(&mut sections, &mut roots, item.range.start);
+            }
+            sections.push(item);
+        } else {
+            match sections.last_mut() {
+                Some(section) => section.children.push(item),
+                None => roots.push(item),
+            }
+        }
+    }
+    while !sections.is_empty() {
+        close_section(&mut sections, &mut roots, end);
+    }
+    roots
+}
+
+fn section_level(section: &OutlineItem) -> usize {
+    match section.kind {
+        OutlineKind::Heading(level) => level,
+        _ => 0,
+    }
+}
+
+/// Ends the innermost section before `end` and moves it into its parent.
+fn close_section(sections: &mut Vec<OutlineItem>, roots: &mut Vec<OutlineItem>, end: usize) {
+    let Some(mut section) = sections.pop() else {
+        return;
+    };
+    section.range.end = end.max(section.range.end);
+    match sections.last_mut() {
+        Some(parent) => parent.children.push(section),
+        None => roots.push(section),
+    }
+}
@@ -0,0 +1,192 @@
+//! # selection
+//!
+//! The ranges an editor steps through when the selection is expanded from the cursor.
+//!
+//! They follow the nodes around the cursor, like identifier, argument, call and block in code.
+//! Markup has no nodes for words, paragraphs and sections, those ranges are added so expanding
+//! goes word, strong or emphasized text, paragraph, list item and section. Every range is
+//! larger than the one before it, nodes that span just their only child are skipped.
+
+use std::ops::Range;
+
+use typst_syntax::{LinkedNode, SyntaxKind, SyntaxNode};
+
+use crate::node::node_walker;
+use crate::outline::{outline, OutlineItem, OutlineKind};
+
+/// The ranges around an offset, from the innermost to the whole document.
+///
+/// # Example
+/// ```
+/// use typst_analyzer_analysis::selection::selection_ranges;
+///
+/// let text = "= Intro\nSome *very bold* text.\n\n= Next";
+/// let root = typst_syntax::parse(text);
+/// let ranges: Vec<&str> = selection_ranges(&root, text.find("bold").unwrap_or(0))
+///     .into_iter()
+///     .filter_map(|range| text.get(range))
+///     .collect();
+/// assert_eq!(
+///     ranges,
+///     [
+///         "bold",
+///         "very bold",
+///         "*very bold*",
+///         "Some *very bold* text.",
+///         "= Intro\nSome *very bold* text.",
+///         text,
+///     ]
+/// );
+/// ```
+pub fn selection_ranges(root: &SyntaxNode, offset: usize) -> Vec<Range<usize>> {
+    let mut ancestors = node_walker(offset, root);
+    // Between two leaves the walker takes the one before the cursor, but at the start of a word
+    // the word is meant and not the space or the `.` in front of it
+    if ancestors
+        .back()
+        .is_some_and(|leaf| leaf.range().end == offset && !is_name(leaf.kind()))
+    {
+        let after = node_walker(offset + 1, root);
+        if after
+            .back()
+            .is_some_and(|leaf| leaf.range().start == offset && is_name(leaf.kind()))
+        {
+            ancestors = after;
+        }
+    }
+
+    let mut candidates = Vec::new();
+    candidates.push(0..root.len());
+    if let Some(leaf) = ancestors.back() {
+        candidates.extend(word(leaf, offset));
+    }
+    for node in &ancestors {
+        candidates.push(node.range());
+        if node.kind() == SyntaxKind::Markup {
+            candidates.extend(paragraph(node, offset));
+        }
+    }
+    sections(
+        &outline(root),
+        &root.clone().into_text(),
+        offset,
+        &mut candidates,
+    );
+
+    candidates.retain(|range| range.start <= offset && offset <= range.end);
+    candidates.sort_by_key(|range| (range.len(), range.start));
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    for range in candidates {
+        let grows = ranges.last().is_none_or(|last| {
+            range.start <= last.start && last.end <= range.end && range.len() > last.len()
+        });
+        if grows {
+            ranges.push(range);
+        }
+    }
+    ranges
+}
+
+/// Whether a leaf is a word, a name or a value, rather than punctuation or space.
+fn is_name(kind: SyntaxKind) -> bool {
+    kind.is_keyword()
+        || matches!(
+            kind,
+            SyntaxKind::Text
+                | SyntaxKind::Ident
+                | SyntaxKind::MathIdent
+                | SyntaxKind::Str
+                | SyntaxKind::Int
+                | SyntaxKind::Float
+                | SyntaxKind::Numeric
+                | SyntaxKind::Bool
+                | SyntaxKind::Label
+                | SyntaxKind::RefMarker
+        )
+}
+
+/// The word at an offset in a text leaf.
+fn word(leaf: &LinkedNode, offset: usize) -> Option<Range<usize>> {
+    if !matches!(leaf.kind(), SyntaxKind::Text | SyntaxKind::Str) {
+        return None;
+    }
+    let text = leaf.text();
+    let start = leaf.offset();
+    let cursor = offset.checked_sub(start)?;
+    let before = text.get(..cursor)?;
+    let after = text.get(cursor..)?;
+    let is_word = |c: char| c.is_alphanumeric() || c == '_' || c == '-';
+    let word_start = before.trim_end_matches(is_word).len();
+    let word_end = cursor + (after.len() - after.trim_start_matches(is_word).len());
+    (word_start < word_end).then_some(start + word_start..start + word_end)
+}
+
+/// The paragraph of markup an offset is in, the children between blank lines and headings.
+fn paragraph(markup: &LinkedNode, offset: usize) -> Option<Range<usize>> {
+    let mut start = None;
+    let mut end = None;
+    for child in markup.children() {
+        let breaks = matches!(child.kind(), SyntaxKind::Parbreak | SyntaxKind::Heading);
+        if child.range().end <= offset && breaks {
+            start = None;
+            continue;
+        }
+        if child.range().start >= offset && breaks {
+            break;
+        }
+        if child.kind().is_trivia() && start.is_none() {
+            continue;
+        }
+        start.get_or_insert(child.range().start);
+        if !child.kind().is_trivia() {
+            end = Some(child.range().end);
+        }
+    }
+    Some(start?..end?)
+}
+
+/// The sections of the headings around an offset, without the blank lines at their end.
+fn sections(items: &[OutlineItem], text: &str, offset: usize, candidates: &mut Vec<Range<usize>>) {
+    for item in items {
+        if !matches!(item.kind, OutlineKind::Heading(_)) || !item.range.contains(&offset) {
+            continue;
+        }
+        let end = text
+            .get(item.range.clone())
+            .map_or(item.range.end, |section| {
+                item.range.start + section.trim_end().len()
+            });
+        candidates.push(item.range.start..end);
+        sections(&item.children, text, offset, candidates);
+    }
+}
+
+#[test]
+fn selection_ranges_test() {
+    let text = "#let x = f(a, b: calc.max(1, 2))\n- item *one*";
+    let root = typst_syntax::parse(text);
+    let at = |needle: &str| text.find(needle).unwrap_or(0);
+    let ranges = |offset: usize| -> Vec<&str> {
+        selection_ranges(&root, offset)
+            .into_iter()
+            .filter_map(|range| text.get(range))
+            .collect()
+    };
+    assert_eq!(
+        ranges(at("max")),
+        [
+            "max",
+            "calc.max",
+            "calc.max(1, 2)",
+            "b: calc.max(1, 2)",
+            "(a, b: calc.max(1, 2))",
+            "f(a, b: calc.max(1, 2))",
+            "let x = f(a, b: calc.max(1, 2))",
+            text,
+        ]
+    );
+    assert_eq!(
+        ranges(at("one")),
+        ["one", "*one*", "item *one*", "- item *one*", text]
+    );
+}
@@ -1,100 +1,285 @@
-//! The backend module contains the Backend struct that holds the client, the document map and the
-//! AST map. It also contains the implementation of the LanguageServer trait for the Backend
-//! struct.
+//! The backend module contains the Backend struct that holds the client and the open documents.
+//! It also contains the implementation of the LanguageServer trait for the Backend struct.
 
-use std::sync::Arc;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::Duration;
 
 use dashmap::DashMap;
 use serde_json::Value;
+use tokio::task::JoinHandle;
 use tower_lsp::jsonrpc::Result;
 use tower_lsp::lsp_types::*;
 use tower_lsp::{Client, LanguageServer};
-use typst_syntax::{FileId, Source, VirtualPath};
 
 use crate::code_actions::handle::TypstCodeActions;
 use crate::completion::TypstCompletion;
+use crate::config::project::ConfigFile;
+use crate::config::ConfigCache;
 use crate::definition::HandleDefinitions;
+use crate::document::Document;
+use crate::document_symbols::HandleDocumentSymbols;
 use crate::error_ctx::TypError;
+use crate::folding_ranges::HandleFoldingRanges;
 use crate::hover::HandleHover;
-use crate::symbols::{Symbol, SymbolTable};
+use crate::position::PositionEncoding;
+use crate::references::HandleReferences;
+use crate::rename::HandleRename;
+use crate::selection_ranges::HandleSelectionRanges;
+use crate::semantic_tokens::{legend, HandleSemanticTokens, SemanticTokensCache};
+use crate::symbols::SymbolTable;
 use crate::typ_logger;
+use crate::workspace::project::PIN_MAIN_COMMAND;
+use crate::workspace::resources::ResourceCache;
+use crate::workspace::symbols::SymbolIndex;
+use crate::workspace_symbols::HandleWorkspaceSymbols;
 
-/// The backend struct that holds the client, the document map and the AST map
-#[derive(Debug)]
+/// The backend struct that holds the client and the open documents
+///
+/// Cloning is cheap, all state is shared, so background tasks can hold their own handle.
+#[derive(Debug, Clone)]
 pub struct Backend {
     pub client: Client,
-    // Maps a document URI to its text content
-    pub doc_map: DashMap<String, String>,
-    // Maping a document URI to its parsed AST of type SyntaxNode is what is have seen in other
-    // lsps but i am maping to to type Source cuz
+    // Maps a document URI to its text, version and parsed source. Maping to type Source instead
+    // of SyntaxNode cuz
     // 1. i can easly parse it to AST
     // 2, it contains additional metadata (we need ast and span id in some cases)
-    pub ast_map: DashMap<String, Source>,
-    // Maps symbol names to Symbol metadata
-    pub symbol_table: Arc<DashMap<String, Symbol>>, // WIP!
+    pub documents: Arc<DashMap<Url, Document>>,
+    // Labels and references of every analysed file, by file and symbol kind
+    pub symbol_index: Arc<SymbolIndex>,
+    // Position encoding negotiated with the client during initialize
+    pub position_encoding: Arc<OnceLock<PositionEncoding>>,
+    // Debounces diagnostics runs per document
+    pub diagnostics: Arc<DiagnosticsScheduler>,
+    // Capabilities the client announced during initialize
+    pub client_capabilities: Arc<OnceLock<ClientCapabilities>>,
+    // Root folders of the workspace in the order the client listed them, their Typst files are
+    // indexed in the background
+    pub workspace_folders: Arc<RwLock<Vec<Url>>>,
+    // Settings sent by the client, as they were sent
+    pub settings: Arc<RwLock<Value>>,
+    // Project and global config files that have been read, by path
+    pub config_files: Arc<DashMap<PathBuf, ConfigFile>>,
+    // Project config files and merged settings already looked up
+    pub config_cache: Arc<ConfigCache>,
+    // Main file pinned with the pin main command
+    pub pinned_main: Arc<RwLock<Option<Url>>>,
+    // Semantic tokens sent last for every document, the base of the next delta
+    pub semantic_tokens: Arc<SemanticTokensCache>,
+    // Bibliography keys, images and fonts found so far, until the watcher reports a change
+    pub resources: Arc<ResourceCache>,
 }
 
-/// Helper function to convert a Position to an offset in the text
+/// Delay between the last change of a document and computing its diagnostics.
+pub const DEFAULT_DIAGNOSTICS_DELAY: Duration = Duration::from_millis(300);
+
+/// Keeps track of the pending diagnostics run of every document.
 ///
-/// Offset is the number of characters from the start of the text
-pub fn position_to_offset(text: &str, position: Position) -> Option<usize> {
-    let mut offset = 0;
-    // Iterate over the lines and characters in the text
-    for (line_idx, line) in text.lines().enumerate() {
-        if line_idx == position.line as usize {
-            // If the line index matches the position line, return the offset
-            return Some(offset + position.character as usize);
-        }
-        offset += line.len() + 1; // +1 for the newline character
-    }
-    None
+/// Every change schedules a new run after a delay and aborts the previous one, so while the user
+/// is typing only the run for the last change goes through.
+#[derive(Debug)]
+pub struct DiagnosticsScheduler {
+    pending: DashMap<Url, JoinHandle<()>>,
+    delay_ms: AtomicU64,
+}
+
+impl Default for DiagnosticsScheduler {
+    fn default() -> Self {
+        Self {
+            pending: DashMap::new(),
+            delay_ms: AtomicU64::new(DEFAULT_DIAGNOSTICS_DELAY.as_millis() as u64),
+        }
+    }
+}
+
+impl DiagnosticsScheduler {
+    /// The delay used for runs scheduled after a change.
+    pub fn delay(&self) -> Duration {
+        Duration::from_millis(self.delay_ms.load(Ordering::Relaxed))
+    }
+
+    pub fn set_delay(&self, delay: Duration) {
+        self.delay_ms
+            .store(delay.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// Runs `run` for a document after `delay`, aborting the pending run of the document.
+    fn schedule<F>(&self, uri: Url, delay: Duration, run: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let task = tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            run.await;
+        });
+        if let Some(previous) = self.pending.insert(uri, task) {
+            previous.abort();
+        }
+    }
+
+    /// Aborts the pending run of a document, if any.
+    fn cancel(&self, uri: &Url) {
+        if let Some((_, previous)) = self.pending.remove(uri) {
+            previous.abort();
+        }
+    }
 }
 
 impl Backend {
-    /// funciton to handle did change requests
-    pub async fn handle_did_change(&self, params: DidChangeTextDocumentParams) {
-        let uri = params.text_document.uri.to_string();
-        let populate_symbol_table_re = self.populate_symbol_table(params.clone());
-        match populate_symbol_table_re {
-            Ok(_) => {}
-            Err(err) => typ_logger!("{}", TypError::NonCriticalError(err.to_string().as_str())),
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            documents: Arc::new(DashMap::new()),
+            symbol_index: Arc::new(SymbolIndex::default()),
+            position_encoding: Arc::new(OnceLock::new()),
+            diagnostics: Arc::new(DiagnosticsScheduler::default()),
+            client_capabilities: Arc::new(OnceLock::new()),
+            workspace_folders: Arc::new(RwLock::new(Vec::new())),
+            settings: Arc::new(RwLock::new(Value::Null)),
+            config_files: Arc::new(DashMap::new()),
+            config_cache: Arc::new(ConfigCache::default()),
+            pinned_main: Arc::new(RwLock::new(None)),
+            semantic_tokens: Arc::new(SemanticTokensCache::default()),
+            resources: Arc::new(ResourceCache::default()),
         }
-        // Check if the document exists in the document map with key (uri) and collect the document if exists
-        if let Some(mut doc) = self.doc_map.get_mut(&uri) {
-            for change in params.content_changes {
-                // Get the document content
-                let mut doc_ctx = doc.value().clone();
-                // Get the range of the change
-                if let Some(range) = change.range {
-                    // Get the start and end positions of the range
-                    let start = range.start;
-                    let end = range.end;
-                    // Convert the position to an offset
-                    let start_idx = position_to_offset(&doc_ctx, start).unwrap_or(0);
-                    let end_idx = position_to_offset(&doc_ctx, end).unwrap_or(doc_ctx.len());
-                    // Replace the text in the range with the new text
-                    doc_ctx.replace_range(start_idx..end_idx, &change.text);
-                } else {
-                    // If range is None, replace the whole text
-                    doc_ctx = change.text.clone();
-                }
-                // Update the document with the new content
-                *doc.value_mut() = doc_ctx.clone();
-                // Parse the document content to generate the AST
-                if let Some(path_from_uri) = &uri.strip_prefix("file://") {
-                    let ast =
-                        Source::new(FileId::new(None, VirtualPath::new(path_from_uri)), doc_ctx);
-                    // Update the AST map with the new AST
-                    self.ast_map.insert(uri.clone(), ast);
+    }
+
+    /// A backend whose client is not connected, for tests. Notifications to the client are
+    /// dropped since it never initializes.
+    #[cfg(test)]
+    pub(crate) fn detached() -> Self {
+        let mut backend = None;
+        let _ = tower_lsp::LspService::new(|client| {
+            let created = Backend::new(client);
+            backend = Some(created.clone());
+            created
+        });
+        backend.unwrap_or_else(|| unreachable!("the service is built right away"))
+    }
+
+    /// The position encoding negotiated with the client, UTF-16 until initialize has run.
+    pub fn position_encoding(&self) -> PositionEncoding {
+        self.position_encoding.get().copied().unwrap_or_default()
+    }
+
+    /// The capabilities of the client, empty until initialize has run.
+    pub fn client_capabilities(&self) -> ClientCapabilities {
+        self.client_capabilities.get().cloned().unwrap_or_default()
+    }
+
+    /// The workspace folders, in the order the client listed them.
+    pub fn workspace_folders(&self) -> Vec<Url> {
+        match self.workspace_folders.read() {
+            Ok(folders) => folders.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    /// Adds a workspace folder after the others, returns whether it was not known yet.
+    pub(crate) fn add_workspace_folder(&self, folder: Url) -> bool {
+        let mut folders = match self.workspace_folders.write() {
+            Ok(folders) => folders,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if folders.contains(&folder) {
+            return false;
+        }
+        folders.push(folder);
+        true
+    }
+
+    /// Forgets a workspace folder, the order of the others is kept.
+    pub(crate) fn remove_workspace_folder(&self, folder: &Url) {
+        match self.workspace_folders.write() {
+            Ok(mut folders) => folders.retain(|known| known != folder),
+            Err(poisoned) => poisoned.into_inner().retain(|known| known != folder),
+        }
+    }
+
+    /// Indexes the given workspace folders in a background task.
+    fn spawn_workspace_indexing(&self, folders: Vec<Url>) {
+        if folders.is_empty() {
+            return;
+        }
+        let backend = self.clone();
+        tokio::spawn(async move { backend.index_workspace_folders(folders).await });
+    }
+
+    /// Recomputes the derived analysis of a document after it was opened or changed.
+    fn analyze_document(&self, uri: &Url) {
+        if let Some(mut doc) = self.documents.get_mut(uri) {
+            match self.populate_symbol_table(uri, &doc) {
+                Ok(symbols) => doc.analysis.symbols = symbols,
+                Err(err) => {
+                    typ_logger!("{}", TypError::NonCriticalError(err.to_string().as_str()))
                 }
             }
-            if let Ok(diagnostics) = self.provide_diagnostics(params.text_document.uri.clone()) {
-                // Publish the diagnostics to the client
-                self.client
-                    .publish_diagnostics(params.text_document.uri.clone(), diagnostics, None)
-                    .await;
+        }
+    }
+
+    /// Schedules a diagnostics run for a document after `delay`, replacing any pending run.
+    pub(crate) fn schedule_diagnostics(&self, uri: Url, delay: Duration) {
+        let backend = self.clone();
+        let task_uri = uri.clone();
+        self.diagnostics.schedule(uri, delay, async move {
+            backend.publish_document_diagnostics(task_uri).await;
+        });
+    }
+
+    /// Schedules diagnostics for a document and the other open files of the documents it is part
+    /// of, since its labels may be referenced from them.
+    pub(crate) fn schedule_document_diagnostics(&self, uri: Url, delay: Duration) {
+        for file in self.document_files(&uri) {
+            if file != uri && self.documents.contains_key(&file) {
+                self.schedule_diagnostics(file, delay);
+            }
+        }
+        self.schedule_diagnostics(uri, delay);
+    }
+
+    /// Computes and publishes the diagnostics of the current version of a document.
+    async fn publish_document_diagnostics(&self, uri: Url) {
+        let Some(version) = self.documents.get(&uri).map(|doc| doc.version) else {
+            return;
+        };
+        let diagnostics = match self.provide_diagnostics(uri.clone()) {
+            Ok(diagnostics) => diagnostics,
+            Err(err) => {
+                typ_logger!("{}", TypError::NonCriticalError(err.to_string().as_str()));
+                return;
             }
+        };
+        // A newer version arrived while the diagnostics were computed, its own run will publish
+        if self.documents.get(&uri).map(|doc| doc.version) != Some(version) {
+            return;
         }
+        // Publish the diagnostics to the client
+        self.client
+            .publish_diagnostics(uri, diagnostics, Some(version))
+            .await;
+    }
+
+    /// funciton to handle did change requests
+    pub async fn handle_did_change(&self, params: DidChangeTextDocumentParams) {
+        let uri = params.text_document.uri;
+        // Check if the document exists in the document map with key (uri) and collect the document if exists
+        let applied = match self.documents.get_mut(&uri) {
+            Some(mut doc) => doc.apply_changes(
+                params.text_document.version,
+                params.content_changes,
+                self.position_encoding(),
+            ),
+            None => return,
+        };
+        if let Err(err) = applied {
+            typ_logger!("{}", TypError::NonCriticalError(err.to_string().as_str()));
+            return;
+        }
+        self.analyze_document(&uri);
+        self.index_dependencies(&uri).await;
+        self.schedule_document_diagnostics(uri, self.diagnostics.delay());
     }
 }
 
@@ -109,13 +294,45 @@ impl Backend {}
 #[tower_lsp::async_trait]
 impl LanguageServer for Backend {
     /// Initialize the language server
-    async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+    async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
+        let position_encoding = PositionEncoding::negotiate(
+            params
+                .capabilities
+                .general
+                .as_ref()
+                .and_then(|general| general.position_encodings.as_deref()),
+        );
+        let position_encoding = *self.position_encoding.get_or_init(|| position_encoding);
+        // Older clients only send a root uri
+        #[allow(deprecated)]
+        let folders: Vec<Url> = match params.workspace_folders {
+            Some(folders) => folders.into_iter().map(|folder| folder.uri).collect(),
+            None => params.root_uri.into_iter().collect(),
+        };
+        for folder in folders {
+            self.add_workspace_folder(folder);
+        }
+        let _ = self.client_capabilities.set(params.capabilities);
+        if let Some(options) = params.initialization_options {
+            self.update_settings(options);
+        }
         Ok(InitializeResult {
             server_info: None,
             capabilities: ServerCapabilities {
+                position_encoding: Some(position_encoding.kind()),
                 document_formatting_provider: Some(OneOf::Left(true)),
                 inlay_hint_provider: Some(OneOf::Left(true)),
                 definition_provider: Some(OneOf::Left(true)),
+                references_provider: Some(OneOf::Left(true)),
+                document_symbol_provider: Some(OneOf::Left(true)),
+                workspace_symbol_provider: Some(OneOf::Right(WorkspaceSymbolOptions {
+                    resolve_provider: Some(true),
+                    work_done_progress_options: WorkDoneProgressOptions::default(),
+                })),
+                rename_provider: Some(OneOf::Right(RenameOptions {
+                    prepare_provider: Some(true),
+                    work_done_progress_options: WorkDoneProgressOptions::default(),
+                })),
                 hover_provider: Some(HoverProviderCapability::Simple(true)),
                 code_action_provider: Some(CodeActionProviderCapability::Options(
                     CodeActionOptions {
@@ -145,10 +362,22 @@ impl LanguageServer for Backend {
                     commands: vec![
                         "dummy.do_something".to_owned(),
                         "add_dummy_bib_entry".to_owned(),
+                        PIN_MAIN_COMMAND.to_owned(),
                     ],
                     work_done_progress_options: Default::default(),
                 }),
                 folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
+                selection_range_provider: Some(SelectionRangeProviderCapability::Simple(true)),
+                semantic_tokens_provider: Some(
+                    SemanticTokensServerCapabilities::SemanticTokensOptions(
+                        SemanticTokensOptions {
+                            legend: legend(),
+                            range: Some(true),
+                            full: Some(SemanticTokensFullOptions::Delta { delta: Some(true) }),
+                            work_done_progress_options: WorkDoneProgressOptions::default(),
+                        },
+                    ),
+                ),
                 workspace: Some(WorkspaceServerCapabilities {
                     workspace_folders: Some(WorkspaceFoldersServerCapabilities {
                         supported: Some(true),
@@ -192,13 +421,68 @@ impl LanguageServer for Backend {
         self.client
             .log_message(MessageType::INFO, "Language Server initialized!")
             .await;
+        self.spawn_workspace_indexing(self.workspace_folders());
+        if let Err(err) = self.register_file_watchers().await {
+            typ_logger!("{}", TypError::NonCriticalError(err.to_string().as_str()));
+        }
+        if let Err(err) = self.register_configuration_change().await {
+            typ_logger!("{}", TypError::NonCriticalError(err.to_string().as_str()));
+        }
+        self.load_global_config().await;
+        self.diagnostics
+            .set_delay(self.settings().diagnostics.delay());
+    }
+
+    /// Handle folding range requests
+    async fn folding_range(&self, params: FoldingRangeParams) -> Result<Option<Vec<FoldingRange>>> {
+        match self.provide_folding_ranges(params) {
+            Ok(ranges) => Ok(Some(ranges)),
+            Err(_) => Ok(None),
+        }
     }
 
-    async fn folding_range(
+    /// Handle selection range requests
+    async fn selection_range(
         &self,
-        _params: FoldingRangeParams,
-    ) -> Result<Option<Vec<FoldingRange>>> {
-        Ok(None)
+        params: SelectionRangeParams,
+    ) -> Result<Option<Vec<SelectionRange>>> {
+        match self.provide_selection_ranges(params) {
+            Ok(ranges) => Ok(Some(ranges)),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Handle semantic tokens requests for a whole document
+    async fn semantic_tokens_full(
+        &self,
+        params: SemanticTokensParams,
+    ) -> Result<Option<SemanticTokensResult>> {
+        match self.provide_semantic_tokens_full(params) {
+            Ok(tokens) => Ok(Some(SemanticTokensResult::Tokens(tokens))),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Handle semantic tokens requests for the changes since the last request
+    async fn semantic_tokens_full_delta(
+        &self,
+        params: SemanticTokensDeltaParams,
+    ) -> Result<Option<SemanticTokensFullDeltaResult>> {
+        match self.provide_semantic_tokens_delta(params) {
+            Ok(delta) => Ok(Some(delta)),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Handle semantic tokens requests for a range
+    async fn semantic_tokens_range(
+        &self,
+        params: SemanticTokensRangeParams,
+    ) -> Result<Option<SemanticTokensRangeResult>> {
+        match self.provide_semantic_tokens_range(params) {
+            Ok(tokens) => Ok(Some(SemanticTokensRangeResult::Tokens(tokens))),
+            Err(_) => Ok(None),
+        }
     }
 
     async fn formatting(&self, params: DocumentFormattingParams) -> Result<Option<Vec<TextEdit>>> {
@@ -233,6 +517,54 @@ impl LanguageServer for Backend {
         }
     }
 
+    /// Handle find references requests
+    async fn references(&self, params: ReferenceParams) -> Result<Option<Vec<Location>>> {
+        match self.provide_references(params) {
+            Ok(references) => Ok(Some(references)),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Handle prepare rename requests
+    async fn prepare_rename(
+        &self,
+        params: TextDocumentPositionParams,
+    ) -> Result<Option<PrepareRenameResponse>> {
+        match self.provide_prepare_rename(params) {
+            Ok(response) => Ok(Some(response)),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Handle rename requests, an invalid new name is reported to the user
+    async fn rename(&self, params: RenameParams) -> Result<Option<WorkspaceEdit>> {
+        match self.provide_rename(params) {
+            Ok(edit) => Ok(Some(edit)),
+            Err(err) => Err(tower_lsp::jsonrpc::Error::invalid_params(err.to_string())),
+        }
+    }
+
+    /// Handle document symbol requests
+    async fn document_symbol(
+        &self,
+        params: DocumentSymbolParams,
+    ) -> Result<Option<DocumentSymbolResponse>> {
+        match self.provide_document_symbols(params) {
+            Ok(symbols) => Ok(Some(symbols)),
+            Err(_) => Ok(None),
+        }
+    }
+
+    // `workspace/symbol` is answered by the `WorkspaceSymbolService` around the server
+
+    /// Handle workspace symbol resolve requests
+    async fn symbol_resolve(&self, params: WorkspaceSymbol) -> Result<WorkspaceSymbol> {
+        match self.resolve_workspace_symbol(params.clone()) {
+            Ok(symbol) => Ok(symbol),
+            Err(_) => Ok(params),
+        }
+    }
+
     /// Handle hover requests
     async fn hover(&self, params: HoverParams) -> Result<Option<Hover>> {
         let definitions_result = self.provide_hover_ctx(params);
@@ -257,31 +589,29 @@ impl LanguageServer for Backend {
 
     /// Handle code action requests
     async fn code_action(&self, params: CodeActionParams) -> Result<Option<CodeActionResponse>> {
-        let uri = params.text_document.uri.to_string();
         self.client
             .log_message(MessageType::INFO, "Code action requested")
             .await;
 
-        if let Some(doc) = self.doc_map.get(&uri) {
-            let content = doc.value();
+        // Copy the text out so the document is not locked while the actions are computed.
+        let content = self
+            .documents
+            .get(&params.text_document.uri)
+            .map(|doc| doc.text.to_string());
+        if let Some(content) = &content {
             let range = params.range;
 
             let actions =
                 self.generate_code_actions(content, range, params.text_document.uri.clone());
-            let ctx_restlt =
-                self.calculate_code_actions_for_bib(content, range, params.text_document.uri.clone());
+            let ctx_restlt = self.calculate_code_actions_for_bib(
+                content,
+                range,
+                params.text_document.uri.clone(),
+            );
 
             match ctx_restlt {
                 Ok(mut ctx) => match actions {
                     Ok(mut actions) => {
-                        if let Ok(label_dig_re) =
-                             self.missing_label_error(params.text_document.uri.clone())
-                        {
-                            for i in label_dig_re {
-                                actions.push(i.1);
-                            }
-                            // actions.append(&mut label_dig_re);
-                        }
                         actions.append(&mut ctx);
                         if !actions.is_empty() {
                             self.client
@@ -300,27 +630,20 @@ impl LanguageServer for Backend {
 
     /// Handle did open requests
     async fn did_open(&self, params: DidOpenTextDocumentParams) {
-        let text_document = params.text_document.clone();
-        self.doc_map
-            .insert(text_document.uri.to_string(), text_document.text.clone());
-
-        if let Some(doc) = self.doc_map.get_mut(&text_document.uri.to_string()) {
-            // Update the AST map with the new AST
-            if let Some(path_from_uri) = &text_document.uri.to_string().strip_prefix("file://") {
-                let ast = Source::new(
-                    FileId::new(None, VirtualPath::new(path_from_uri)),
-                    doc.value().to_string(),
-                );
-                // Update the AST map with the new AST
-                self.ast_map.insert(text_document.uri.to_string(), ast);
-            }
-        }
-        if let Ok(diagnostics) = self.provide_diagnostics(params.text_document.uri.clone()) {
-            // Publish the diagnostics to the client
-            self.client
-                .publish_diagnostics(params.text_document.uri.clone(), diagnostics, None)
-                .await;
-        }
+        let text_document = params.text_document;
+        self.documents.insert(
+            text_document.uri.clone(),
+            Document::new(
+                &text_document.uri,
+                text_document.text,
+                text_document.version,
+            ),
+        );
+        self.load_project_config(&text_document.uri).await;
+        self.analyze_document(&text_document.uri);
+        self.index_dependencies(&text_document.uri).await;
+        // Nothing to debounce on open, publish right away
+        self.schedule_document_diagnostics(text_document.uri.clone(), Duration::ZERO);
         self.client
             .log_message(
                 MessageType::INFO,
@@ -346,8 +669,20 @@ impl LanguageServer for Backend {
 
     /// Handle did close requests
     async fn did_close(&self, params: DidCloseTextDocumentParams) {
-        let uri = params.text_document.uri.to_string();
-        self.doc_map.remove(&uri);
+        let uri = params.text_document.uri;
+        self.diagnostics.cancel(&uri);
+        self.documents.remove(&uri);
+        self.semantic_tokens.remove(&uri);
+        // Files in the workspace stay indexed with their content on disk
+        if self.is_in_workspace(&uri) {
+            self.index_file_from_disk(&uri).await;
+        } else {
+            self.symbol_index.remove(&uri);
+        }
+        // Diagnostics of a closed document are stale, clear them in the client
+        self.client
+            .publish_diagnostics(uri.clone(), Vec::new(), None)
+            .await;
         self.client
             .log_message(MessageType::INFO, format!("Closed file: {}", uri))
             .await;
@@ -359,21 +694,39 @@ impl LanguageServer for Backend {
     }
 
     /// Handle did change workspace folders requests
-    async fn did_change_workspace_folders(&self, _: DidChangeWorkspaceFoldersParams) {
+    async fn did_change_workspace_folders(&self, params: DidChangeWorkspaceFoldersParams) {
+        for removed in params.event.removed {
+            self.remove_workspace_folder(&removed.uri);
+        }
+        // Forget closed files that are no longer part of any folder
+        self.symbol_index
+            .retain(|uri| self.documents.contains_key(uri) || self.is_in_workspace(uri));
+        let added: Vec<Url> = params
+            .event
+            .added
+            .into_iter()
+            .map(|folder| folder.uri)
+            .filter(|uri| self.add_workspace_folder(uri.clone()))
+            .collect();
+        // Config files are not searched for above the workspace folder of a document
+        self.config_cache.clear();
+        self.spawn_workspace_indexing(added);
         self.client
             .log_message(MessageType::INFO, "Workspace folders changed!")
             .await;
     }
 
     /// Handle did change configuration requests
-    async fn did_change_configuration(&self, _: DidChangeConfigurationParams) {
+    async fn did_change_configuration(&self, params: DidChangeConfigurationParams) {
+        self.reload_settings(params.settings).await;
         self.client
             .log_message(MessageType::INFO, "Configuration changed!")
             .await;
     }
 
     /// Handle did change watched files requests
-    async fn did_change_watched_files(&self, _: DidChangeWatchedFilesParams) {
+    async fn did_change_watched_files(&self, params: DidChangeWatchedFilesParams) {
+        self.handle_watched_files(params.changes).await;
         self.client
             .log_message(MessageType::INFO, "Watched files have changed!")
             .await;
@@ -409,3 +762,34 @@ impl LanguageServer for Backend {
         Ok(None)
     }
 }
+
+#[tokio::test]
+async fn diagnostics_debounce_test() {
+    use std::sync::atomic::AtomicUsize;
+
+    let scheduler = DiagnosticsScheduler::default();
+    scheduler.set_delay(Duration::from_millis(20));
+    let runs = Arc::new(AtomicUsize::new(0));
+    let uri = Url::parse("file:///main.typ");
+    assert!(uri.is_ok());
+    let Ok(uri) = uri else { return };
+    // Five changes typed faster than the delay, only the last one gets its diagnostics
+    for _ in 0..5 {
+        let runs = runs.clone();
+        scheduler.schedule(uri.clone(), scheduler.delay(), async move {
+            runs.fetch_add(1, Ordering::Relaxed);
+        });
+        tokio::time::sleep(Duration::from_millis(2)).await;
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(runs.load(Ordering::Relaxed), 1);
+
+    // A run that is cancelled does not happen at all
+    let cancelled = runs.clone();
+    scheduler.schedule(uri.clone(), scheduler.delay(), async move {
+        cancelled.fetch_add(1, Ordering::Relaxed);
+    });
+    scheduler.cancel(&uri);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(runs.load(Ordering::Relaxed), 1);
+}
@@ -3,11 +3,12 @@ use std::collections::HashMap;
 use anyhow::Error;
 use regex::Regex;
 use tower_lsp::lsp_types::*;
-use typst_analyzer_analysis::bibliography::{get_bib_keys, new_bib_key};
+use typst_analyzer_analysis::bibliography::new_bib_key;
 use typst_analyzer_analysis::dict::*;
 
 use crate::backend::Backend;
 use crate::typ_logger;
+use crate::workspace::project::PIN_MAIN_COMMAND;
 
 pub(crate) trait TypstCodeActions {
     fn get_table_parameters(&self) -> HashMap<String, String>;
@@ -247,10 +248,10 @@ impl TypstCodeActions for Backend {
         &self,
         _content: &str,
         _range: Range,
-        _uri: Url,
+        uri: Url,
     ) -> Result<Vec<CodeActionOrCommand>, Error> {
         let mut actions = Vec::new();
-        let _keys = get_bib_keys()?;
+        let _keys = self.bib_keys(&self.bibliography_path(Some(&uri))?);
         actions.push(CodeActionOrCommand::Command(Command {
             title: "add a dummy bibliography entry for this item".to_owned(),
             command: "add_dummy_bib_entry".to_owned(),
@@ -274,8 +275,15 @@ impl Backend {
     pub async fn execute_custom_command(&self, params: &ExecuteCommandParams) -> Result<(), Error> {
         match params.command.as_str() {
             "this" => typ_logger!("this"),
+            PIN_MAIN_COMMAND => {
+                self.pin_main(params.arguments.as_slice())?;
+                // Diagnostics depend on the document a file belongs to
+                self.apply_settings().await;
+            }
             "add_dummy_bib_entry" => {
-                if let Ok(true) = new_bib_key("this-is-by-typst-analyzer") {
+                if let Ok(true) =
+                    new_bib_key(&self.bibliography_path(None)?, "this-is-by-typst-analyzer")
+                {
                     typ_logger!("this is from add_dummy_bib_entry");
                 }
                 typ_logger!("this is from add_dummy_bib_entry");
@@ -1,10 +1,13 @@
 use std::collections::VecDeque;
+use std::path::PathBuf;
 
-use crate::backend::{position_to_offset, Backend};
+use crate::backend::Backend;
+use crate::position::position_to_offset;
 use anyhow::Error;
 use tower_lsp::lsp_types::{CompletionItem, TextDocumentPositionParams};
 use typst_analyzer_analysis::completion::generate_completions;
 use typst_analyzer_analysis::node::node_walker;
+use typst_analyzer_analysis::ProjectPaths;
 use typst_syntax::LinkedNode;
 
 pub(crate) trait TypstCompletion {
@@ -19,15 +22,29 @@ impl TypstCompletion for Backend {
         &self,
         params: TextDocumentPositionParams,
     ) -> Result<Vec<CompletionItem>, Error> {
-        let uri: String = params.text_document.uri.to_string();
-        if let Some(text) = self.doc_map.get(&uri) {
-            if let Some(position) = position_to_offset(&text, params.position) {
-                if let Some(ast_map_ctx) = self.ast_map.get(&uri) {
-                    let syntaxnode = &ast_map_ctx.value().text();
-                    let parsed = typst_syntax::parse(syntaxnode);
-                    let linked_node: VecDeque<LinkedNode> = node_walker(position, &parsed);
-                    return generate_completions(linked_node.into());
-                }
+        let uri = &params.text_document.uri;
+        let root = self.project_root(uri);
+        let font_paths: Vec<PathBuf> = self
+            .settings_for(uri)
+            .font_paths
+            .into_iter()
+            .filter_map(|path| self.resolve_in_project(Some(uri), path))
+            .collect();
+        let project = ProjectPaths {
+            images: root
+                .as_deref()
+                .map(|root| self.project_images(root))
+                .unwrap_or_default(),
+            fonts: self.fonts(&font_paths),
+            root,
+        };
+        if let Some(doc) = self.documents.get(&params.text_document.uri) {
+            if let Some(position) =
+                position_to_offset(&doc.text, params.position, self.position_encoding())
+            {
+                // The cached source is kept up to date incrementally, no need to reparse.
+                let linked_node: VecDeque<LinkedNode> = node_walker(position, doc.source.root());
+                return generate_completions(linked_node.into(), &project);
             }
         }
         Ok(Vec::new())
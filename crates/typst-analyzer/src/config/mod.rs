@@ -0,0 +1,483 @@
+//! Settings of the server.
+//!
+//! Settings come from three places, each overriding the ones before it:
+//!
+//! 1. the global `typst-analyzer.toml` in the config directory, e.g. `~/.config/typst-analyzer/`,
+//! 2. the client, read from `initializationOptions` during `initialize` and pulled again with
+//!    `workspace/configuration` whenever the client reports a change,
+//! 3. the project `typst-analyzer.toml` closest to the document, see [`project`].
+//!
+//! Every field has a default, so each layer only has to set what it wants to change. Layers are
+//! merged key by key, a project that sets `formatter.max-width` keeps the `tab-spaces` of the
+//! client. The client sends the settings in camelCase:
+//!
+//! ```json
+//! {
+//!   "formatter": { "maxWidth": 100 },
+//!   "inlayHints": { "linebreaks": false },
+//!   "lints": { "missingLabel": "warning" },
+//!   "diagnostics": { "delay": 500 },
+//!   "bibliography": "refs/bibliography.yml",
+//!   "mainFile": "main.typ",
+//!   "export": { "format": "pdf", "outputPath": "out", "when": "onSave" }
+//! }
+//! ```
+
+pub mod project;
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{anyhow, Error};
+use dashmap::DashMap;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+use tower_lsp::lsp_types::notification::{DidChangeConfiguration, Notification};
+use tower_lsp::lsp_types::{ConfigurationItem, DiagnosticSeverity, Registration, Url};
+use typst_analyzer_analysis::bibliography::bibliography_file_path;
+
+use crate::backend::{Backend, DEFAULT_DIAGNOSTICS_DELAY};
+use crate::error_ctx::TypError;
+use crate::typ_logger;
+use crate::workspace::symbols::DependencyKind;
+
+/// The section of the client configuration holding our settings.
+pub const CONFIG_SECTION: &str = "typst-analyzer";
+
+/// What the settings of a document resolve to, kept until a config file or the client settings
+/// change. Settings are looked up for every hint, diagnostic, completion and format request.
+#[derive(Debug, Default)]
+pub struct ConfigCache {
+    /// The project config file of every directory looked up, `None` if it has none.
+    project_configs: DashMap<PathBuf, Option<PathBuf>>,
+    /// The merged settings by project config file, `None` for documents outside of a project.
+    settings: DashMap<Option<PathBuf>, Settings>,
+}
+
+impl ConfigCache {
+    /// Forgets everything, after a config file was created, changed or deleted.
+    pub fn clear(&self) {
+        self.project_configs.clear();
+        self.settings.clear();
+    }
+}
+
+/// All settings of the server.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Settings {
+    pub formatter: FormatterSettings,
+    pub inlay_hints: InlayHintSettings,
+    pub lints: LintSettings,
+    pub diagnostics: DiagnosticsSettings,
+    /// Bibliography files, relative to the project root. Searched for if not set.
+    #[serde(deserialize_with = "one_or_many")]
+    pub bibliography: Vec<PathBuf>,
+    /// Entry point of the document, relative to the project root.
+    pub main_file: Option<PathBuf>,
+    /// Directories with fonts in addition to the system fonts.
+    pub font_paths: Vec<PathBuf>,
+    pub export: ExportSettings,
+}
+
+/// Accepts a single path as well as a list of paths.
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<PathBuf>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(PathBuf),
+        Many(Vec<PathBuf>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(path) => vec![path],
+        OneOrMany::Many(paths) => paths,
+    })
+}
+
+impl Settings {
+    /// Reads the settings from a JSON value sent by the client.
+    ///
+    /// `null` stands for the default settings. The settings may be wrapped in an object with
+    /// [`CONFIG_SECTION`] as key, as some clients send the whole configuration.
+    pub fn from_value(value: Value) -> Result<Self, serde_json::Error> {
+        match unwrap_section(value) {
+            Value::Null => Ok(Settings::default()),
+            value => serde_json::from_value(value),
+        }
+    }
+}
+
+/// Takes the settings out of an object with [`CONFIG_SECTION`] as key.
+fn unwrap_section(value: Value) -> Value {
+    match value {
+        Value::Object(mut map) if map.contains_key(CONFIG_SECTION) => {
+            map.remove(CONFIG_SECTION).unwrap_or_default()
+        }
+        value => value,
+    }
+}
+
+/// Merges a settings layer into `base`. Objects are merged key by key, any other value set in
+/// `layer` replaces the one in `base`.
+pub fn merge_settings(base: &mut Value, layer: Value) {
+    match (base, layer) {
+        (_, Value::Null) => {}
+        (Value::Object(base), Value::Object(layer)) => {
+            for (key, value) in layer {
+                merge_settings(base.entry(key).or_insert(Value::Null), value);
+            }
+        }
+        (base, layer) => *base = layer,
+    }
+}
+
+/// Options passed on to typstyle.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct FormatterSettings {
+    /// Number of spaces per indentation level.
+    pub tab_spaces: usize,
+    /// Maximum width of a line.
+    pub max_width: usize,
+    /// Maximum number of consecutive blank lines.
+    pub blank_lines_upper_bound: usize,
+}
+
+impl Default for FormatterSettings {
+    fn default() -> Self {
+        let config = typstyle_core::Config::default();
+        Self {
+            tab_spaces: config.tab_spaces,
+            max_width: config.max_width,
+            blank_lines_upper_bound: config.blank_lines_upper_bound,
+        }
+    }
+}
+
+impl FormatterSettings {
+    pub fn typstyle_config(&self) -> typstyle_core::Config {
+        typstyle_core::Config {
+            tab_spaces: self.tab_spaces,
+            max_width: self.max_width,
+            blank_lines_upper_bound: self.blank_lines_upper_bound,
+        }
+    }
+}
+
+/// Which inlay hints are shown.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct InlayHintSettings {
+    pub linebreaks: bool,
+    pub labels: bool,
+    pub references: bool,
+}
+
+impl Default for InlayHintSettings {
+    fn default() -> Self {
+        Self {
+            linebreaks: true,
+            labels: true,
+            references: true,
+        }
+    }
+}
+
+/// The severity of every lint.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct LintSettings {
+    /// A reference to a label that does not exist.
+    pub missing_label: LintLevel,
+}
+
+impl Default for LintSettings {
+    fn default() -> Self {
+        Self {
+            missing_label: LintLevel::Error,
+        }
+    }
+}
+
+/// When diagnostics are computed.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DiagnosticsSettings {
+    /// Milliseconds between the last change of a document and computing its diagnostics.
+    pub delay: u64,
+}
+
+impl Default for DiagnosticsSettings {
+    fn default() -> Self {
+        Self {
+            delay: DEFAULT_DIAGNOSTICS_DELAY.as_millis() as u64,
+        }
+    }
+}
+
+impl DiagnosticsSettings {
+    pub fn delay(&self) -> Duration {
+        Duration::from_millis(self.delay)
+    }
+}
+
+/// How a lint is reported, `off` disables it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LintLevel {
+    Off,
+    Hint,
+    Info,
+    Warning,
+    Error,
+}
+
+impl LintLevel {
+    /// The severity of the diagnostics, `None` if the lint is disabled.
+    pub fn severity(self) -> Option<DiagnosticSeverity> {
+        match self {
+            LintLevel::Off => None,
+            LintLevel::Hint => Some(DiagnosticSeverity::HINT),
+            LintLevel::Info => Some(DiagnosticSeverity::INFORMATION),
+            LintLevel::Warning => Some(DiagnosticSeverity::WARNING),
+            LintLevel::Error => Some(DiagnosticSeverity::ERROR),
+        }
+    }
+}
+
+/// When, where and to what the document is exported.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ExportSettings {
+    pub format: ExportFormat,
+    /// Directory the output is written to, next to the main file if not set.
+    pub output_path: Option<PathBuf>,
+    pub when: ExportWhen,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ExportFormat {
+    #[default]
+    Pdf,
+    Png,
+    Svg,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ExportWhen {
+    #[default]
+    Never,
+    OnSave,
+    OnType,
+}
+
+impl Backend {
+    /// The settings that apply outside of any project: the global config file and the client.
+    pub fn settings(&self) -> Settings {
+        self.merged_settings(None)
+    }
+
+    /// The settings for a document, including the project config file closest to it.
+    pub fn settings_for(&self, uri: &Url) -> Settings {
+        self.merged_settings(Some(uri))
+    }
+
+    fn merged_settings(&self, uri: Option<&Url>) -> Settings {
+        let project = uri.and_then(|uri| self.project_config_path(uri));
+        if let Some(settings) = self.config_cache.settings.get(&project) {
+            return settings.clone();
+        }
+        let mut value = Value::Null;
+        if let Some(global) = self.global_config() {
+            merge_settings(&mut value, global.layer);
+        }
+        let client = match self.settings.read() {
+            Ok(client) => client.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        };
+        merge_settings(&mut value, client);
+        if let Some(config) = project
+            .as_deref()
+            .and_then(|path| self.project_config(path))
+        {
+            merge_settings(&mut value, config.layer);
+        }
+        // Every layer is checked on its own, merging valid layers should not fail
+        let settings = Settings::from_value(value).unwrap_or_else(|err| {
+            typ_logger!("invalid merged settings: {}", err);
+            Settings::default()
+        });
+        self.config_cache.settings.insert(project, settings.clone());
+        settings
+    }
+
+    /// Replaces the client settings with `value`, keeping the current ones if it is invalid.
+    pub(crate) fn update_settings(&self, value: Value) {
+        if let Err(err) = Settings::from_value(value.clone()) {
+            typ_logger!("invalid settings: {}", err);
+            return;
+        }
+        let value = unwrap_section(value);
+        match self.settings.write() {
+            Ok(mut current) => *current = value,
+            Err(poisoned) => *poisoned.into_inner() = value,
+        }
+        self.config_cache.settings.clear();
+    }
+
+    /// Whether the client answers `workspace/configuration` requests.
+    fn supports_configuration_pull(&self) -> bool {
+        self.client_capabilities()
+            .workspace
+            .and_then(|workspace| workspace.configuration)
+            .unwrap_or(false)
+    }
+
+    /// Asks the client to notify us about configuration changes.
+    ///
+    /// Without the registration clients like VS Code do not send `didChangeConfiguration`.
+    pub(crate) async fn register_configuration_change(&self) -> Result<(), Error> {
+        let supported = self
+            .client_capabilities()
+            .workspace
+            .and_then(|workspace| workspace.did_change_configuration)
+            .and_then(|change| change.dynamic_registration)
+            .unwrap_or(false);
+        if supported {
+            self.client
+                .register_capability(vec![Registration {
+                    id: "typst-analyzer/configuration".to_owned(),
+                    method: DidChangeConfiguration::METHOD.to_owned(),
+                    register_options: None,
+                }])
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Reloads the settings after a configuration change and applies them to open documents.
+    ///
+    /// The settings are pulled with `workspace/configuration` if the client supports it, the
+    /// notification may only carry a part of them. Otherwise the pushed settings are used.
+    pub(crate) async fn reload_settings(&self, pushed: Value) {
+        let value = if self.supports_configuration_pull() {
+            let items = vec![ConfigurationItem {
+                scope_uri: None,
+                section: Some(CONFIG_SECTION.to_owned()),
+            }];
+            match self.client.configuration(items).await {
+                Ok(mut values) if !values.is_empty() => values.swap_remove(0),
+                Ok(_) => Value::Null,
+                Err(err) => {
+                    typ_logger!("{}", TypError::NonCriticalError(err.to_string().as_str()));
+                    return;
+                }
+            }
+        } else {
+            pushed
+        };
+        let previous = self.settings();
+        self.update_settings(value);
+        // The global config file is not watched, pick up its changes together with the client's
+        self.load_global_config().await;
+        if self.settings() != previous {
+            self.apply_settings().await;
+        }
+    }
+
+    /// Updates everything derived from the settings in the client.
+    pub(crate) async fn apply_settings(&self) {
+        self.diagnostics
+            .set_delay(self.settings().diagnostics.delay());
+        let open: Vec<Url> = self.documents.iter().map(|doc| doc.key().clone()).collect();
+        for uri in open {
+            self.schedule_diagnostics(uri, Duration::ZERO);
+        }
+        let refresh_hints = self
+            .client_capabilities()
+            .workspace
+            .and_then(|workspace| workspace.inlay_hint)
+            .and_then(|hints| hints.refresh_support)
+            .unwrap_or(false);
+        if refresh_hints {
+            if let Err(err) = self.client.inlay_hint_refresh().await {
+                typ_logger!("{}", TypError::NonCriticalError(err.to_string().as_str()));
+            }
+        }
+    }
+
+    /// The bibliography file: the first configured one, the first one passed to `#bibliography`
+    /// in the document, or the first `bibliography.yml` in the project.
+    pub(crate) fn bibliography_path(&self, uri: Option<&Url>) -> Result<PathBuf, Error> {
+        let settings = self.merged_settings(uri);
+        if let Some(path) = settings.bibliography.into_iter().next() {
+            return self.resolve_in_project(uri, path).ok_or(anyhow!(
+                "no project root to resolve the bibliography against"
+            ));
+        }
+        let cited = uri
+            .into_iter()
+            .flat_map(|uri| self.document_files(uri))
+            .find_map(|file| {
+                self.symbol_index
+                    .dependencies(&file, DependencyKind::Bibliography)
+                    .into_iter()
+                    .find_map(|bibliography| bibliography.to_file_path().ok())
+            });
+        match cited {
+            Some(path) => Ok(path),
+            None => {
+                // Resolving a bare `.` yields the project root
+                let root = self
+                    .resolve_in_project(uri, PathBuf::from("."))
+                    .ok_or(anyhow!("no project root to search for a bibliography"))?;
+                bibliography_file_path(&root)
+            }
+        }
+    }
+}
+
+#[test]
+fn settings_test() {
+    let settings = Settings::from_value(serde_json::json!({
+        "typst-analyzer": {
+            "formatter": { "maxWidth": 100 },
+            "inlayHints": { "linebreaks": false },
+            "lints": { "missingLabel": "off" },
+            "diagnostics": { "delay": 50 },
+            "mainFile": "thesis.typ",
+            "export": { "format": "svg", "when": "onType" }
+        }
+    }));
+    assert!(settings.is_ok());
+    let Ok(settings) = settings else { return };
+    assert_eq!(settings.formatter.max_width, 100);
+    assert_eq!(settings.formatter.tab_spaces, 2);
+    assert!(!settings.inlay_hints.linebreaks);
+    assert!(settings.inlay_hints.labels);
+    assert_eq!(settings.lints.missing_label.severity(), None);
+    assert_eq!(settings.diagnostics.delay(), Duration::from_millis(50));
+    assert_eq!(settings.main_file, Some(PathBuf::from("thesis.typ")));
+    assert_eq!(
+        (settings.export.format, settings.export.when),
+        (ExportFormat::Svg, ExportWhen::OnType)
+    );
+    assert_eq!(settings.export.output_path, None);
+    assert!(Settings::from_value(Value::Null).is_ok_and(|s| s == Settings::default()));
+
+    let mut value = serde_json::json!({ "formatter": { "maxWidth": 100, "tabSpaces": 4 } });
+    merge_settings(
+        &mut value,
+        serde_json::json!({ "formatter": { "maxWidth": 120 }, "bibliography": "refs.yml" }),
+    );
+    let merged = Settings::from_value(value);
+    assert!(merged.is_ok());
+    let Ok(merged) = merged else { return };
+    assert_eq!(
+        (merged.formatter.max_width, merged.formatter.tab_spaces),
+        (120, 4)
+    );
+    assert_eq!(merged.bibliography, vec![PathBuf::from("refs.yml")]);
+}
@@ -0,0 +1,418 @@
+//! `typst-analyzer.toml` configuration files.
+//!
+//! A project config file applies to every document in its directory and below it, the file
+//! closest to the document wins. The global config file in the config directory applies to every
+//! document. Both have the same fields as the client settings, in kebab-case, with `entry` for
+//! the main file:
+//!
+//! ```toml
+//! entry = "main.typ"
+//! bibliography = ["refs.yml", "more.bib"]
+//! font-paths = ["fonts"]
+//!
+//! [lints]
+//! missing-label = "warning"
+//!
+//! [formatter]
+//! max-width = 100
+//!
+//! [export]
+//! format = "pdf"
+//! output-path = "out"
+//! when = "onSave"
+//! ```
+//!
+//! Relative paths are relative to the directory of the config file. Problems in a config file are
+//! published as diagnostics on the file itself, an invalid file is ignored. Unknown keys, often
+//! misspelled ones, are only warned about.
+
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use ropey::Rope;
+use serde_json::Value;
+use tower_lsp::lsp_types::{Diagnostic, DiagnosticSeverity, Url};
+
+use super::Settings;
+use crate::backend::Backend;
+use crate::position::{offsets_to_range, PositionEncoding};
+use crate::typ_logger;
+
+/// The name of project and global config files.
+pub const CONFIG_FILE: &str = "typst-analyzer.toml";
+
+/// Settings that hold paths, resolved against the directory of the config file.
+const PATH_SETTINGS: [&[&str]; 4] = [
+    &["mainFile"],
+    &["bibliography"],
+    &["fontPaths"],
+    &["export", "outputPath"],
+];
+
+/// A parsed config file.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigFile {
+    /// The settings of the file in the layout of the client settings, `null` if it is invalid.
+    pub layer: Value,
+    /// Problems found in the file.
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl ConfigFile {
+    pub fn parse(path: &Path, text: &str, encoding: PositionEncoding) -> Self {
+        let rope = Rope::from_str(text);
+        let diagnostic = |range: Range<usize>, severity, message| Diagnostic {
+            range: offsets_to_range(&rope, &range, encoding).unwrap_or_default(),
+            severity: Some(severity),
+            source: Some("typst-analyzer".to_owned()),
+            message,
+            ..Default::default()
+        };
+        let error = |range: Range<usize>, message: String| ConfigFile {
+            layer: Value::Null,
+            diagnostics: vec![diagnostic(range, DiagnosticSeverity::ERROR, message)],
+        };
+        let unknown = |range: Range<usize>, key: &str| {
+            diagnostic(
+                range,
+                DiagnosticSeverity::WARNING,
+                format!("unknown setting `{key}`"),
+            )
+        };
+        // Every setting is serialized, so the defaults hold every known key
+        let known = serde_json::to_value(Settings::default()).unwrap_or_default();
+        let mut warnings = Vec::new();
+
+        let table = match text.parse::<toml::Table>() {
+            Ok(table) => table,
+            Err(err) => return error(err.span().unwrap_or_default(), err.message().to_owned()),
+        };
+        let mut layer = serde_json::Map::new();
+        for (key, value) in table {
+            // `entry` reads better than `main-file` in a project file
+            let camel_key = match key.as_str() {
+                "entry" => "mainFile".to_owned(),
+                key => camel_case(key),
+            };
+            match (known.get(&camel_key), &value) {
+                (None, _) => warnings.push(unknown(key_range(text, &key), &key)),
+                (Some(Value::Object(fields)), toml::Value::Table(table)) => {
+                    for nested in table.keys() {
+                        if !fields.contains_key(&camel_case(nested)) {
+                            let range = nested_key_range(text, &key, nested);
+                            warnings.push(unknown(range, &format!("{key}.{nested}")));
+                        }
+                    }
+                }
+                _ => {}
+            }
+            let value = match serde_json::to_value(value) {
+                Ok(value) => camel_case_keys(value),
+                Err(err) => return error(key_range(text, &key), err.to_string()),
+            };
+            // Check each key on its own so that the error points at it
+            let single = Value::Object(serde_json::Map::from_iter([(
+                camel_key.clone(),
+                value.clone(),
+            )]));
+            if let Err(err) = Settings::from_value(single) {
+                return error(key_range(text, &key), format!("invalid `{key}`: {err}"));
+            }
+            layer.insert(camel_key, value);
+        }
+        let mut layer = Value::Object(layer);
+        if let Some(dir) = path.parent() {
+            for setting in PATH_SETTINGS {
+                if let Some(value) = setting
+                    .iter()
+                    .try_fold(&mut layer, |value, key| value.get_mut(*key))
+                {
+                    resolve_paths(value, dir);
+                }
+            }
+        }
+        // The keys come sorted by name, not in the order of the file
+        warnings.sort_by_key(|warning| (warning.range.start.line, warning.range.start.character));
+        ConfigFile {
+            layer,
+            diagnostics: warnings,
+        }
+    }
+}
+
+/// Converts `font-paths` into `fontPaths`.
+fn camel_case(key: &str) -> String {
+    let mut parts = key.split('-');
+    let mut camel = parts.next().unwrap_or_default().to_owned();
+    for part in parts {
+        let mut chars = part.chars();
+        if let Some(first) = chars.next() {
+            camel.extend(first.to_uppercase());
+            camel.push_str(chars.as_str());
+        }
+    }
+    camel
+}
+
+fn camel_case_keys(value: Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .map(|(key, value)| (camel_case(&key), camel_case_keys(value)))
+                .collect(),
+        ),
+        value => value,
+    }
+}
+
+/// Makes relative paths in a string or an array of strings absolute.
+fn resolve_paths(value: &mut Value, dir: &Path) {
+    match value {
+        Value::String(path) => {
+            *path = dir.join(&*path).to_string_lossy().into_owned();
+        }
+        Value::Array(paths) => paths.iter_mut().for_each(|path| resolve_paths(path, dir)),
+        _ => {}
+    }
+}
+
+/// The range of the line defining a top level key or table, the whole file if it is not found.
+fn key_range(text: &str, key: &str) -> Range<usize> {
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        let defines = trimmed
+            .strip_prefix('[')
+            .unwrap_or(trimmed)
+            .strip_prefix(key)
+            .is_some_and(|rest| rest.trim_start().starts_with(['=', '.', ']']));
+        if defines {
+            let start = offset + line.len() - trimmed.len();
+            return start..offset + line.trim_end().len();
+        }
+        offset += line.len();
+    }
+    0..text.len()
+}
+
+/// The range of the line defining a key of a table, the line of the table if it is not found.
+fn nested_key_range(text: &str, table: &str, key: &str) -> Range<usize> {
+    let header = key_range(text, table);
+    let mut offset = header.end;
+    for line in text[header.end..].split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with('[') {
+            break;
+        }
+        let defines = trimmed
+            .strip_prefix(key)
+            .is_some_and(|rest| rest.trim_start().starts_with(['=', '.']));
+        if defines {
+            let start = offset + line.len() - trimmed.len();
+            return start..offset + line.trim_end().len();
+        }
+        offset += line.len();
+    }
+    header
+}
+
+impl Backend {
+    /// The global config file, `~/.config/typst-analyzer/typst-analyzer.toml` on Linux.
+    fn global_config_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("typst-analyzer").join(CONFIG_FILE))
+    }
+
+    pub(crate) fn global_config(&self) -> Option<ConfigFile> {
+        let path = Self::global_config_path()?;
+        self.config_files.get(&path).map(|file| file.clone())
+    }
+
+    /// The project config file closest to a document, if there is one.
+    ///
+    /// Like the project root, it is not searched for above the workspace folder of the document.
+    /// The answer is cached per directory, whether a config file is found or not, until a config
+    /// file or the workspace folders change.
+    pub(crate) fn project_config_path(&self, uri: &Url) -> Option<PathBuf> {
+        let path = uri.to_file_path().ok()?;
+        let dir = path.parent()?;
+        if let Some(config) = self.config_cache.project_configs.get(dir) {
+            return config.clone();
+        }
+        let folder = self.workspace_folder_of(dir);
+        let mut config = None;
+        for dir in dir.ancestors() {
+            let candidate = dir.join(CONFIG_FILE);
+            if self.config_files.contains_key(&candidate) || candidate.is_file() {
+                config = Some(candidate);
+                break;
+            }
+            if Some(dir) == folder.as_deref() {
+                break;
+            }
+        }
+        self.config_cache
+            .project_configs
+            .insert(dir.to_owned(), config.clone());
+        config
+    }
+
+    /// A project config file, read from disk if it is not loaded yet.
+    pub(crate) fn project_config(&self, path: &Path) -> Option<ConfigFile> {
+        if let Some(file) = self.config_files.get(path) {
+            return Some(file.clone());
+        }
+        let text = std::fs::read_to_string(path).ok()?;
+        let file = ConfigFile::parse(path, &text, self.position_encoding());
+        self.config_files.insert(path.to_owned(), file.clone());
+        Some(file)
+    }
+
+    /// Reads a config file and publishes its problems, or forgets it if it was deleted.
+    pub(crate) async fn load_config_file(&self, path: &Path) {
+        let Ok(uri) = Url::from_file_path(path) else {
+            return;
+        };
+        let diagnostics = match tokio::fs::read_to_string(path).await {
+            Ok(text) => {
+                let file = ConfigFile::parse(path, &text, self.position_encoding());
+                let diagnostics = file.diagnostics.clone();
+                self.config_files.insert(path.to_owned(), file);
+                diagnostics
+            }
+            Err(err) => {
+                typ_logger!("failed to read {}: {}", path.display(), err);
+                self.config_files.remove(path);
+                Vec::new()
+            }
+        };
+        // A created or deleted file moves the closest config file of the directories below it
+        self.config_cache.clear();
+        self.client
+            .publish_diagnostics(uri, diagnostics, None)
+            .await;
+    }
+
+    pub(crate) async fn load_global_config(&self) {
+        if let Some(path) = Self::global_config_path() {
+            if path.is_file() || self.config_files.contains_key(&path) {
+                self.load_config_file(&path).await;
+            }
+        }
+    }
+
+    /// Loads the project config file of a document, if it is not loaded yet.
+    pub(crate) async fn load_project_config(&self, uri: &Url) {
+        if let Some(path) = self.project_config_path(uri) {
+            if !self.config_files.contains_key(&path) {
+                self.load_config_file(&path).await;
+            }
+        }
+    }
+}
+
+#[test]
+fn config_file_test() {
+    let path = Path::new("/project/typst-analyzer.toml");
+    let file = ConfigFile::parse(
+        path,
+        "entry = \"main.typ\"\nfont-paths = [\"fonts\"]\n\n[formatter]\nmax-width = 100\n\n[export]\noutput-path = \"out\"\nwhen = \"onSave\"\n",
+        PositionEncoding::Utf16,
+    );
+    assert!(file.diagnostics.is_empty());
+    let settings = Settings::from_value(file.layer);
+    assert!(settings.is_ok());
+    let Ok(settings) = settings else { return };
+    assert_eq!(settings.main_file, Some(PathBuf::from("/project/main.typ")));
+    assert_eq!(settings.font_paths, vec![PathBuf::from("/project/fonts")]);
+    assert_eq!(settings.formatter.max_width, 100);
+    assert_eq!(
+        settings.export.output_path,
+        Some(PathBuf::from("/project/out"))
+    );
+    assert_eq!(settings.export.when, super::ExportWhen::OnSave);
+
+    // Unknown keys are warned about, the rest of the file still applies
+    let file = ConfigFile::parse(
+        path,
+        "mainfile = \"main.typ\"\n\n[lints]\nmissing-label = \"off\"\nmising-label = \"off\"\n",
+        PositionEncoding::Utf16,
+    );
+    let warnings: Vec<_> = file
+        .diagnostics
+        .iter()
+        .map(|diagnostic| (diagnostic.severity, diagnostic.range.start.line))
+        .collect();
+    let warning = Some(DiagnosticSeverity::WARNING);
+    assert_eq!(warnings, [(warning, 0), (warning, 4)]);
+    let settings = Settings::from_value(file.layer);
+    assert!(settings.is_ok_and(|settings| settings.lints.missing_label.severity().is_none()));
+
+    let file = ConfigFile::parse(
+        path,
+        "entry = \"main.typ\"\n[lints]\nmissing-label = \"loud\"\n",
+        PositionEncoding::Utf16,
+    );
+    assert!(file.layer.is_null());
+    assert_eq!(
+        file.diagnostics
+            .first()
+            .map(|diagnostic| diagnostic.range.start.line),
+        Some(1)
+    );
+}
+
+#[tokio::test]
+async fn config_cache_test() {
+    use crate::workspace::fs::temp_workspace;
+
+    let root = temp_workspace(
+        "config-cache",
+        &[
+            (CONFIG_FILE, "[formatter]\nmax-width = 100\n"),
+            ("chapters/one.typ", ""),
+        ],
+    );
+    let backend = Backend::detached();
+    let one = Url::from_file_path(root.join("chapters/one.typ"));
+    assert!(one.is_ok());
+    let Ok(one) = one else { return };
+    assert_eq!(
+        backend.project_config_path(&one),
+        Some(root.join(CONFIG_FILE))
+    );
+    assert_eq!(backend.settings_for(&one).formatter.max_width, 100);
+
+    // A config file written behind our back is not seen, the lookup and the settings are cached
+    let nested = root.join("chapters").join(CONFIG_FILE);
+    assert!(std::fs::write(&nested, "[formatter]\nmax-width = 60\n").is_ok());
+    assert_eq!(
+        backend.project_config_path(&one),
+        Some(root.join(CONFIG_FILE))
+    );
+    assert_eq!(backend.settings_for(&one).formatter.max_width, 100);
+
+    // Until the watcher reports it
+    backend.load_config_file(&nested).await;
+    assert_eq!(backend.project_config_path(&one), Some(nested.clone()));
+    assert_eq!(backend.settings_for(&one).formatter.max_width, 60);
+
+    // Deleting it falls back to the config file above, new client settings apply right away
+    assert!(std::fs::remove_file(&nested).is_ok());
+    backend.load_config_file(&nested).await;
+    assert_eq!(backend.settings_for(&one).formatter.max_width, 100);
+    backend.update_settings(serde_json::json!({ "formatter": { "tabSpaces": 8 } }));
+    assert_eq!(backend.settings_for(&one).formatter.tab_spaces, 8);
+
+    // The search stops at the workspace folder
+    let folder = Url::from_directory_path(root.join("chapters"));
+    assert!(folder.is_ok());
+    let Ok(folder) = folder else { return };
+    backend.add_workspace_folder(folder);
+    backend.config_cache.clear();
+    assert_eq!(backend.project_config_path(&one), None);
+    assert_eq!(
+        backend.settings_for(&one).formatter.max_width,
+        super::FormatterSettings::default().max_width
+    );
+    let _ = std::fs::remove_dir_all(root);
+}
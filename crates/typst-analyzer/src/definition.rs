@@ -1,11 +1,11 @@
 use std::collections::HashMap;
 
 use tower_lsp::lsp_types::{GotoDefinitionParams, GotoDefinitionResponse, Location, Url};
-use typst_syntax::SyntaxKind;
 
 use crate::backend::Backend;
 use crate::prelude::*;
-use crate::symbols::range_to_location;
+use crate::symbols::Symbol;
+use crate::workspace::symbols::{SymbolKind, SymbolRole};
 
 pub(crate) trait HandleDefinitions {
     fn provide_definitions(
@@ -30,6 +30,9 @@ impl HandleDefinitions for Backend {
                 }
             }
         }
+        if let Some(definition) = self.definition_at(&uri, pos) {
+            return Ok(GotoDefinitionResponse::Scalar(definition.location));
+        }
         Err(anyhow!("cant find definitions"))
     }
 }
@@ -41,54 +44,102 @@ pub struct DefinitionsMaker {
 }
 
 impl Backend {
+    /// Pairs every reference of the document with the label it points to, using the symbol
+    /// index. Labels are looked up in every file of the documents the file is part of.
     pub fn definitions(&self, uri: Url) -> Result<Vec<DefinitionsMaker>, anyhow::Error> {
-        let mut definitions = Vec::new();
-        let mut ast_labels = HashMap::new();
-        let mut ast_references = HashMap::new();
-        let binding = self.ast_map.get(&uri.to_string());
+        let labels = self.visible_labels(&uri);
+        self.symbol_index
+            .with_file(&uri, |file_symbols| {
+                file_symbols
+                    .all(SymbolKind::Label, SymbolRole::Reference)
+                    .into_iter()
+                    .filter_map(|reference| {
+                        let label = labels.get(&reference.name)?.first()?;
+                        Some(DefinitionsMaker {
+                            location: reference.location.clone(),
+                            response: GotoDefinitionResponse::Scalar(label.location.clone()),
+                        })
+                    })
+                    .collect()
+            })
+            .ok_or(anyhow!("document is not indexed"))
+    }
 
-        if let Some(ast) = &binding {
-            let source = ast.value();
-            for node in ast.root().children() {
-                if node.kind() == SyntaxKind::Label {
-                    // slice out the range of node from ast_map source
-                    if let Some(range) = &source.range(node.span()) {
-                        let loc = range_to_location(uri.clone(), source, range)?;
-                        let ctx = source
-                            .get(range.to_owned())
-                            .ok_or(anyhow!("Failed to get ctx of ast from range"))?;
-                        let label = ctx
-                            .strip_prefix("<")
-                            .ok_or(anyhow!("failed to strip symbols"))?
-                            .strip_suffix(">")
-                            .ok_or(anyhow!("failed to strip symbols"))?;
-                        ast_labels.insert(label, loc);
-                    }
-                }
-                if node.kind() == SyntaxKind::Ref {
-                    // slice out the range of node from ast_map source
-                    if let Some(range) = &source.range(node.span()) {
-                        let loc = range_to_location(uri.clone(), source, range)?;
-                        let ctx = source
-                            .get(range.to_owned())
-                            .ok_or(anyhow!("Failed to get ctx of ast from range"))?;
-                        let reference = ctx
-                            .strip_prefix("@")
-                            .ok_or(anyhow!("failed to strip symbols"))?;
-                        ast_references.insert(reference, loc);
-                    }
+    /// The labels a reference in the file can point to, by name.
+    ///
+    /// A label is visible anywhere in the compiled document, so these are the labels of every
+    /// file reachable through `#include` from the main files of the file. Labels of the file
+    /// itself come first.
+    pub(crate) fn visible_labels(&self, uri: &Url) -> HashMap<String, Vec<Symbol>> {
+        let mut labels: HashMap<String, Vec<Symbol>> = HashMap::new();
+        let others = self
+            .document_files(uri)
+            .into_iter()
+            .filter(|file| file != uri);
+        for file in std::iter::once(uri.clone()).chain(others) {
+            self.symbol_index.with_file(&file, |file_symbols| {
+                for label in file_symbols.all(SymbolKind::Label, SymbolRole::Definition) {
+                    labels
+                        .entry(label.name.clone())
+                        .or_default()
+                        .push(label.clone());
                 }
-            }
-        }
-        for (k, v) in ast_references {
-            if let Some(loc) = ast_labels.get(k) {
-                // definitions.push(GotoDefinitionResponse::Scalar(loc.to_owned()));
-                definitions.push(DefinitionsMaker {
-                    location: v,
-                    response: GotoDefinitionResponse::Scalar(loc.to_owned()),
-                });
-            }
+            });
         }
-        Ok(definitions)
+        labels
     }
 }
+
+#[tokio::test]
+async fn visible_labels_test() {
+    use crate::workspace::fs::temp_workspace;
+
+    let root = temp_workspace(
+        "visible-labels",
+        &[
+            (
+                "main.typ",
+                "= Intro <intro>\n#include \"one.typ\"\n#include \"two.typ\"",
+            ),
+            ("one.typ", "= One <one>\nSee @two."),
+            ("two.typ", "= Two <two>\n= Again <one>"),
+            ("other.typ", "= Other <other>\n= Two <two>"),
+        ],
+    );
+    let folder = Url::from_directory_path(&root);
+    let (one, other) = (
+        Url::from_file_path(root.join("one.typ")),
+        Url::from_file_path(root.join("other.typ")),
+    );
+    assert!(folder.is_ok() && one.is_ok() && other.is_ok());
+    let (Ok(folder), Ok(one), Ok(other)) = (folder, one, other) else {
+        return;
+    };
+    let backend = Backend::detached();
+    backend.add_workspace_folder(folder.clone());
+    backend.index_workspace_folders(vec![folder]).await;
+
+    // Every file of the document is visible, labels of the file itself come first
+    let labels = backend.visible_labels(&one);
+    let mut names: Vec<&String> = labels.keys().collect();
+    names.sort();
+    assert_eq!(names, ["intro", "one", "two"]);
+    let files = |name: &str| -> Vec<Url> {
+        labels
+            .get(name)
+            .into_iter()
+            .flatten()
+            .map(|label| label.location.uri.clone())
+            .collect()
+    };
+    assert_eq!(files("one").first(), Some(&one));
+    assert_eq!(files("one").len(), 2);
+    assert_eq!(files("two").len(), 1);
+
+    // A file that is not included anywhere is a document of its own
+    let labels = backend.visible_labels(&other);
+    let mut names: Vec<&String> = labels.keys().collect();
+    names.sort();
+    assert_eq!(names, ["other", "two"]);
+    let _ = std::fs::remove_dir_all(root);
+}
@@ -1,22 +1,20 @@
+use std::collections::HashSet;
+
 use anyhow::{anyhow, Error};
-use tower_lsp::lsp_types::{
-    CodeActionKind, CodeActionOrCommand, Diagnostic, DiagnosticRelatedInformation,
-    DiagnosticSeverity, Url,
-};
+use tower_lsp::lsp_types::{Diagnostic, DiagnosticRelatedInformation, DiagnosticSeverity, Url};
 use typst_syntax::SyntaxKind;
 
 use crate::backend::Backend;
-use crate::symbols::{find_missing_items, range_to_location, range_to_lsp_range, Symbol};
+use crate::symbols::{range_to_location, range_to_lsp_range};
 use crate::typ_logger;
+use crate::workspace::symbols::{DependencyKind, SymbolKind, SymbolRole};
 
 impl Backend {
     pub(crate) fn provide_diagnostics(&self, uri: Url) -> Result<Vec<Diagnostic>, Error> {
         // Check for unclosed delimiters
         let mut diagnostics = Vec::new(); // check_unclosed_delimiters(&doc);
-        if let Ok(missing_labels) = self.missing_label_error(uri.clone()) {
-            for i in missing_labels {
-                diagnostics.push(i.0);
-            }
+        if let Ok(mut missing_labels) = self.missing_label_error(uri.clone()) {
+            diagnostics.append(&mut missing_labels);
         }
         if let Ok(mut syntax_err) = self.syntax_error(uri.clone()) {
             diagnostics.append(&mut syntax_err);
@@ -26,26 +24,31 @@ impl Backend {
 
     pub(crate) fn syntax_error(&self, uri: Url) -> Result<Vec<Diagnostic>, Error> {
         let mut diagnostics: Vec<Diagnostic> = Vec::new();
-        if let Some(ast) = &self.ast_map.get(&uri.to_string()) {
-            for node in ast.root().children() {
+        if let Some(doc) = &self.documents.get(&uri) {
+            let (source, text) = (&doc.source, &doc.text);
+            for node in source.root().children() {
                 if node.erroneous() {
                     let syntax_err = node.errors();
                     for err in syntax_err {
                         let span = err.span;
-                        if let Some(range) = &ast.value().range(span) {
-                            let source = ast.value();
+                        if let Some(range) = &source.range(span) {
                             let msg = err.message;
                             let hints = err.hints;
                             let mut related_info = Vec::new();
                             for hint in hints.clone() {
                                 related_info.push(DiagnosticRelatedInformation {
-                                    location: range_to_location(uri.clone(), source, range)?,
+                                    location: range_to_location(
+                                        uri.clone(),
+                                        text,
+                                        range,
+                                        self.position_encoding(),
+                                    )?,
                                     message: hint.to_string(),
                                 })
                             }
                             typ_logger!("hints: {:#?}", hints); // i havn't seen any hint yet :(
                             diagnostics.push(Diagnostic {
-                                range: range_to_lsp_range(source, range)?,
+                                range: range_to_lsp_range(text, range, self.position_encoding())?,
                                 severity: Some(DiagnosticSeverity::ERROR),
                                 source: Some("typst-analyzer".to_owned()),
                                 message: msg.to_string(),
@@ -65,91 +68,101 @@ impl Backend {
         Ok(vec![first.to_owned()])
     }
 
-    pub fn missing_label_error(
-        &self,
-        uri: Url,
-    ) -> Result<Vec<(Diagnostic, CodeActionOrCommand)>, Error> {
+    pub fn missing_label_error(&self, uri: Url) -> Result<Vec<Diagnostic>, Error> {
         let mut diagnostic_item = Vec::new();
-        let mut symbol_vec = Vec::new();
-        let mut ast_labels = Vec::new();
-        let mut ast_references = Vec::new();
-        let binding = self.ast_map.get(&uri.to_string());
-
-        if let Some(ast) = &binding {
-            let source = ast.value();
-            for node in ast.root().children() {
-                if node.kind() == SyntaxKind::Label {
-                    // slice out the range of node from ast_map source
-                    if let Some(range) = &ast.value().range(node.span()) {
-                        let ctx = source
-                            .get(range.to_owned())
-                            .ok_or(anyhow!("Failed to get ctx of ast from range"))?;
-                        let label = ctx
-                            .strip_prefix("<")
-                            .ok_or(anyhow!("failed to strip symbols"))?
-                            .strip_suffix(">")
-                            .ok_or(anyhow!("failed to strip symbols"))?;
-                        ast_labels.push(label);
-                    }
-                }
+        let Some(severity) = self.settings_for(&uri).lints.missing_label.severity() else {
+            return Ok(diagnostic_item);
+        };
+        let labels = self.visible_labels(&uri);
+        // A reference may also cite an entry of a bibliography of the document
+        let cited: HashSet<String> = self
+            .document_files(&uri)
+            .iter()
+            .flat_map(|file| {
+                self.symbol_index
+                    .dependencies(file, DependencyKind::Bibliography)
+            })
+            .filter_map(|bibliography| bibliography.to_file_path().ok())
+            .flat_map(|path| self.bib_keys(&path).to_vec())
+            .collect();
+        let references = self
+            .symbol_index
+            .with_file(&uri, |file_symbols| {
+                // Only `@reference` needs the label, a show rule for a missing label does nothing
+                file_symbols
+                    .all(SymbolKind::Label, SymbolRole::Reference)
+                    .into_iter()
+                    .filter(|symbol| symbol.symbol_type == SyntaxKind::Ref)
+                    .cloned()
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
 
-                if node.kind() == SyntaxKind::Ref {
-                    // slice out the range of node from ast_map source
-                    if let Some(range) = &ast.value().range(node.span()) {
-                        let ctx = source
-                            .get(range.to_owned())
-                            .ok_or(anyhow!("Failed to get ctx of ast from range"))?;
-                        let loc = range_to_location(uri.clone(), source, range)?;
-                        let reference = ctx
-                            .strip_prefix("@")
-                            .ok_or(anyhow!("failed to strip symbols"))?;
-                        let symbol = Symbol {
-                            name: reference.to_owned(),
-                            location: loc.clone(),
-                            symbol_type: SyntaxKind::Ref,
-                        };
-                        symbol_vec.push(symbol.clone());
-                        ast_references.push(reference);
-                    }
-                }
-            }
-
-            let missing = find_missing_items(&ast_references, &ast_labels);
-            for symbol in &symbol_vec {
-                if missing.contains(&symbol.name.as_str()) {
-                    let diagnostics = Diagnostic {
-                        range: symbol.location.range,
-                        severity: Some(DiagnosticSeverity::ERROR),
-                        source: Some("typst-analyzer".to_owned()),
-                        message: "reference is missing label".to_owned(),
-                        ..Default::default()
-                    };
-                    let edit = tower_lsp::lsp_types::TextEdit {
-                        range: symbol.location.range,
-                        new_text: "Neovim".to_owned(),
-                    };
-
-                    let workspace_edit = tower_lsp::lsp_types::WorkspaceEdit {
-                        changes: Some(std::collections::HashMap::from([(uri.clone(), vec![edit])])),
-                        document_changes: None,
-                        change_annotations: None,
-                    };
-
-                    let code_action = tower_lsp::lsp_types::CodeAction {
-                        title: "Replace 'VS Code' with 'Neovim'".to_owned(),
-                        kind: Some(CodeActionKind::QUICKFIX),
-                        diagnostics: None,
-                        edit: Some(workspace_edit),
-                        command: None,
-                        is_preferred: Some(true),
-                        disabled: None,
-                        data: None,
-                    };
-                    diagnostic_item
-                        .push((diagnostics, CodeActionOrCommand::CodeAction(code_action)));
-                }
+        for symbol in references {
+            // Only a label that exists nowhere in the compiled document is missing
+            if !labels.contains_key(&symbol.name) && !cited.contains(&symbol.name) {
+                let diagnostics = Diagnostic {
+                    range: symbol.location.range,
+                    severity: Some(severity),
+                    source: Some("typst-analyzer".to_owned()),
+                    message: "reference is missing label".to_owned(),
+                    ..Default::default()
+                };
+                diagnostic_item.push(diagnostics);
             }
         }
         Ok(diagnostic_item)
     }
 }
+
+#[tokio::test]
+async fn missing_label_test() {
+    use crate::workspace::fs::temp_workspace;
+
+    let root = temp_workspace(
+        "missing-label",
+        &[
+            (
+                "main.typ",
+                "= Intro <intro>\n#include \"one.typ\"\n#include \"two.typ\"\n#bibliography(\"refs.bib\")",
+            ),
+            ("one.typ", "= One <one>\nSee @two and @intro."),
+            ("two.typ", "= Two <two>\nSee @one and @missing, as in @knuth."),
+            ("lone.typ", "See @one and @knuth."),
+            (
+                "refs.bib",
+                "@book{knuth,\n  title = {The Art of Computer Programming},\n  author = {Knuth, Donald},\n  year = {1968},\n}\n",
+            ),
+        ],
+    );
+    let folder = Url::from_directory_path(&root);
+    assert!(folder.is_ok());
+    let Ok(folder) = folder else { return };
+    let backend = Backend::detached();
+    backend.add_workspace_folder(folder.clone());
+    backend.index_workspace_folders(vec![folder]).await;
+    let missing = |path: &str| -> Vec<(u32, u32)> {
+        Url::from_file_path(root.join(path))
+            .ok()
+            .and_then(|uri| backend.missing_label_error(uri).ok())
+            .into_iter()
+            .flatten()
+            .map(|diagnostic| {
+                (
+                    diagnostic.range.start.line,
+                    diagnostic.range.start.character,
+                )
+            })
+            .collect()
+    };
+
+    // Labels of other files of the document are found
+    assert!(missing("one.typ").is_empty());
+    assert_eq!(missing("two.typ"), [(1, 13)]);
+    // Labels and bibliography entries of another document are not
+    assert_eq!(missing("lone.typ"), [(0, 4), (0, 13)]);
+
+    backend.update_settings(serde_json::json!({ "lints": { "missingLabel": "off" } }));
+    assert!(missing("two.typ").is_empty());
+    let _ = std::fs::remove_dir_all(root);
+}
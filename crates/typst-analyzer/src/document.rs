@@ -0,0 +1,282 @@
+//! The state the server keeps for every open document.
+//!
+//! The text, the LSP version and the parsed source used to live in separate maps, which could
+//! drift apart when one was updated and the other was not. A [`Document`] holds all of them, so a
+//! document is always inserted, edited and removed as a whole.
+
+use percent_encoding::percent_decode_str;
+use ropey::Rope;
+use thiserror::Error;
+use tower_lsp::lsp_types::{TextDocumentContentChangeEvent, Url};
+use typst_syntax::{FileId, Source, VirtualPath};
+
+use crate::position::{apply_change, PositionEncoding};
+use crate::symbols::Symbol;
+
+/// An open document.
+#[derive(Debug, Clone)]
+pub struct Document {
+    /// The text content, used to convert between LSP positions and byte offsets.
+    pub text: Rope,
+    /// The version sent by the client with the last `didOpen` or `didChange`.
+    pub version: i32,
+    /// The parsed source. Its text is always equal to `text`.
+    pub source: Source,
+    /// Results derived from `source`, recomputed whenever the document changes.
+    pub analysis: DocumentAnalysis,
+}
+
+/// Results derived from the parsed source of a document.
+#[derive(Debug, Clone, Default)]
+pub struct DocumentAnalysis {
+    /// Labels and references found in the document.
+    pub symbols: Vec<Symbol>,
+}
+
+/// Why a `didChange` notification was not applied.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ChangeError {
+    /// The change is not newer than the version we already have.
+    #[error("ignored change with version {received}, document is at version {current}")]
+    OutdatedVersion { current: i32, received: i32 },
+    /// A change range does not exist in the document.
+    #[error("change range out of bounds")]
+    OutOfBounds,
+}
+
+impl Document {
+    pub fn new(uri: &Url, text: String, version: i32) -> Self {
+        let source = Source::new(file_id(uri), text);
+        Self {
+            text: Rope::from_str(source.text()),
+            version,
+            source,
+            analysis: DocumentAnalysis::default(),
+        }
+    }
+
+    /// Applies the content changes of a `didChange` notification.
+    ///
+    /// Changes are rejected if `version` is not newer than the current version, since the client
+    /// would otherwise see edits applied out of order. The changes are applied as a whole: if one
+    /// of them is out of bounds the document is left as it was. The text and the parsed source are
+    /// updated together; only the edited part of the source is reparsed.
+    pub fn apply_changes(
+        &mut self,
+        version: i32,
+        changes: Vec<TextDocumentContentChangeEvent>,
+        encoding: PositionEncoding,
+    ) -> Result<(), ChangeError> {
+        if version <= self.version {
+            return Err(ChangeError::OutdatedVersion {
+                current: self.version,
+                received: version,
+            });
+        }
+        // Every change is applied to a copy of the text first, a range is only known to be valid
+        // after the changes before it. Cloning a rope is cheap, it shares its chunks.
+        let mut edited = self.text.clone();
+        let mut edits = Vec::with_capacity(changes.len());
+        for change in changes {
+            // Replace the text in the range with the new text, or the whole text if range is
+            // None. Positions are converted from the negotiated encoding to byte offsets here.
+            let replaced = apply_change(&mut edited, change.range, &change.text, encoding)
+                .ok_or(ChangeError::OutOfBounds)?;
+            edits.push((change.range.map(|_| replaced), change.text));
+        }
+        for (replaced, text) in edits {
+            // Reparse only the edited part of the source. A full document change is diffed
+            // against the old text by `Source::replace` so that it is incremental too.
+            match replaced {
+                None => {
+                    self.source.replace(&text);
+                }
+                Some(replaced) if is_valid_edit(self.source.text(), &replaced) => {
+                    self.source.edit(replaced, &text);
+                }
+                // The source has drifted from the text, it takes the whole text after the changes
+                Some(_) => {
+                    self.source.replace(&edited.to_string());
+                    break;
+                }
+            }
+        }
+        self.text = edited;
+        self.version = version;
+        Ok(())
+    }
+}
+
+/// Maps a document URI to the `FileId` of its source.
+///
+/// `file:` URIs are decoded with [`Url::to_file_path`], so percent-encoded characters such as
+/// spaces end up in the path as they are on disk. Documents that do not live on disk, like
+/// `untitled:` buffers or `vscode-notebook-cell:` cells, get a virtual path below a directory
+/// named after their scheme, with their path decoded the same way. The fragment is kept as it is
+/// since notebook cells only differ in it.
+pub fn file_id(uri: &Url) -> FileId {
+    // `to_file_path` does not look at the scheme, notebook cells have a path too
+    if uri.scheme() == "file" {
+        if let Ok(path) = uri.to_file_path() {
+            return FileId::new(None, VirtualPath::new(path));
+        }
+    }
+    let decoded = percent_decode_str(uri.path()).decode_utf8_lossy();
+    let mut path = format!("/{}/{}", uri.scheme(), decoded.trim_start_matches('/'));
+    if let Some(fragment) = uri.fragment() {
+        path.push('#');
+        path.push_str(fragment);
+    }
+    FileId::new(None, VirtualPath::new(path))
+}
+
+/// Whether `range` can be passed to `Source::edit`, which panics on ranges that are out of bounds
+/// or split a char. This only fails if the source and the text have drifted apart.
+fn is_valid_edit(text: &str, range: &core::ops::Range<usize>) -> bool {
+    range.start <= range.end
+        && range.end <= text.len()
+        && text.is_char_boundary(range.start)
+        && text.is_char_boundary(range.end)
+}
+
+#[test]
+fn apply_changes_test() {
+    let uri = Url::parse("file:///main.typ");
+    assert!(uri.is_ok());
+    let Ok(uri) = uri else { return };
+    let mut doc = Document::new(&uri, "= Intro <intro>\r\nSee @intro.".to_owned(), 1);
+    let change = TextDocumentContentChangeEvent {
+        range: Some(tower_lsp::lsp_types::Range {
+            start: tower_lsp::lsp_types::Position {
+                line: 1,
+                character: 4,
+            },
+            end: tower_lsp::lsp_types::Position {
+                line: 1,
+                character: 10,
+            },
+        }),
+        range_length: None,
+        text: "@über".to_owned(),
+    };
+    assert_eq!(
+        doc.apply_changes(2, vec![change.clone()], PositionEncoding::Utf16),
+        Ok(())
+    );
+    assert_eq!(doc.source.text(), "= Intro <intro>\r\nSee @über.");
+    assert_eq!(doc.text.to_string(), doc.source.text());
+
+    // A batch with a range out of bounds is not applied at all, not even its first change.
+    let insert = TextDocumentContentChangeEvent {
+        range: Some(tower_lsp::lsp_types::Range {
+            start: tower_lsp::lsp_types::Position {
+                line: 0,
+                character: 0,
+            },
+            end: tower_lsp::lsp_types::Position {
+                line: 0,
+                character: 0,
+            },
+        }),
+        range_length: None,
+        text: "Hi ".to_owned(),
+    };
+    let out_of_bounds = TextDocumentContentChangeEvent {
+        range: Some(tower_lsp::lsp_types::Range {
+            start: tower_lsp::lsp_types::Position {
+                line: 7,
+                character: 0,
+            },
+            end: tower_lsp::lsp_types::Position {
+                line: 7,
+                character: 1,
+            },
+        }),
+        range_length: None,
+        text: String::new(),
+    };
+    assert_eq!(
+        doc.apply_changes(3, vec![insert, out_of_bounds], PositionEncoding::Utf16),
+        Err(ChangeError::OutOfBounds)
+    );
+    assert_eq!(doc.version, 2);
+    assert_eq!(doc.source.text(), "= Intro <intro>\r\nSee @über.");
+    assert_eq!(doc.text.to_string(), doc.source.text());
+
+    // An older version must not be applied on top of a newer one.
+    assert_eq!(
+        doc.apply_changes(2, vec![change], PositionEncoding::Utf16),
+        Err(ChangeError::OutdatedVersion {
+            current: 2,
+            received: 2
+        })
+    );
+}
+
+#[test]
+fn incremental_reparse_test() {
+    let uri = Url::parse("file:///main.typ");
+    assert!(uri.is_ok());
+    let Ok(uri) = uri else { return };
+    let mut doc = Document::new(&uri, "= Intro\n#let x = (1, 2)\nÜber *x*".to_owned(), 1);
+    let change = |range: Option<(u32, u32, u32, u32)>, text: &str| TextDocumentContentChangeEvent {
+        range: range.map(
+            |(start_line, start, end_line, end)| tower_lsp::lsp_types::Range {
+                start: tower_lsp::lsp_types::Position::new(start_line, start),
+                end: tower_lsp::lsp_types::Position::new(end_line, end),
+            },
+        ),
+        range_length: None,
+        text: text.to_owned(),
+    };
+    let edits = [
+        // Typing inside a heading, then replacing a code block across lines and a multi-byte char
+        change(Some((0, 7, 0, 7)), "duction"),
+        change(Some((1, 9, 2, 1)), "[*bold*]\nü"),
+        change(None, "#figure[]\n= Outro"),
+    ];
+    for (version, edit) in (2..).zip(edits) {
+        assert_eq!(
+            doc.apply_changes(version, vec![edit], PositionEncoding::Utf16),
+            Ok(())
+        );
+        // The edited tree is the tree a fresh parse gives
+        assert_eq!(doc.text.to_string(), doc.source.text());
+        assert!(doc
+            .source
+            .root()
+            .spanless_eq(&typst_syntax::parse(doc.source.text())));
+    }
+    assert_eq!(doc.source.text(), "#figure[]\n= Outro");
+
+    let text = "aÜb";
+    assert!(is_valid_edit(text, &(1..3)));
+    assert!(!is_valid_edit(text, &(1..2)));
+    assert!(!is_valid_edit(text, &(3..5)));
+}
+
+#[test]
+fn file_id_test() {
+    let cases = [
+        (
+            "file:///home/me/my%20thesis/main.typ",
+            "/home/me/my thesis/main.typ",
+        ),
+        ("untitled:Untitled-1", "/untitled/Untitled-1"),
+        ("untitled:My%20Notes", "/untitled/My Notes"),
+        (
+            "vscode-notebook-cell:/home/me/notes.ipynb#W1sZmlsZQ%3D%3D",
+            "/vscode-notebook-cell/home/me/notes.ipynb#W1sZmlsZQ%3D%3D",
+        ),
+    ];
+    for (uri, path) in cases {
+        let uri = Url::parse(uri);
+        assert!(uri.is_ok());
+        if let Ok(uri) = uri {
+            assert_eq!(
+                file_id(&uri).vpath().as_rooted_path(),
+                std::path::Path::new(path)
+            );
+        }
+    }
+}
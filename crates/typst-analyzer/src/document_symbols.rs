@@ -0,0 +1,117 @@
+//! The outline of a document, for the outline and breadcrumbs of the editor.
+//!
+//! Clients that support it get the nested outline. Others get a flat list, where the section an
+//! entry belongs to is given as its container.
+
+use ropey::Rope;
+use tower_lsp::lsp_types::{
+    DocumentSymbol, DocumentSymbolParams, DocumentSymbolResponse, Location, SymbolInformation,
+    SymbolKind, Url,
+};
+use typst_analyzer_analysis::outline::{outline, OutlineItem, OutlineKind};
+
+use crate::backend::Backend;
+use crate::position::{offsets_to_range, PositionEncoding};
+use crate::prelude::*;
+
+pub(crate) trait HandleDocumentSymbols {
+    fn provide_document_symbols(
+        &self,
+        params: DocumentSymbolParams,
+    ) -> Result<DocumentSymbolResponse, Error>;
+}
+
+impl HandleDocumentSymbols for Backend {
+    fn provide_document_symbols(
+        &self,
+        params: DocumentSymbolParams,
+    ) -> Result<DocumentSymbolResponse, Error> {
+        let uri = params.text_document.uri;
+        self.with_document(&uri, |doc| {
+            let items = outline(doc.source.root());
+            let encoding = self.position_encoding();
+
+            let hierarchical = self
+                .client_capabilities()
+                .text_document
+                .and_then(|text_document| text_document.document_symbol)
+                .and_then(|symbol| symbol.hierarchical_document_symbol_support)
+                .unwrap_or(false);
+            if hierarchical {
+                let symbols = items
+                    .iter()
+                    .filter_map(|item| document_symbol(item, &doc.text, encoding))
+                    .collect();
+                return Ok(DocumentSymbolResponse::Nested(symbols));
+            }
+            let mut symbols = Vec::new();
+            for item in &items {
+                flatten(item, None, &uri, &doc.text, encoding, &mut symbols);
+            }
+            Ok(DocumentSymbolResponse::Flat(symbols))
+        })
+        .ok_or(anyhow!("document is not open"))?
+    }
+}
+
+pub(crate) fn symbol_kind(kind: OutlineKind) -> SymbolKind {
+    match kind {
+        OutlineKind::Heading(_) => SymbolKind::NAMESPACE,
+        OutlineKind::Figure | OutlineKind::Table => SymbolKind::OBJECT,
+        OutlineKind::Equation => SymbolKind::OPERATOR,
+        OutlineKind::Label => SymbolKind::KEY,
+        OutlineKind::Function => SymbolKind::FUNCTION,
+        OutlineKind::Variable => SymbolKind::VARIABLE,
+        OutlineKind::ShowRule => SymbolKind::EVENT,
+        OutlineKind::SetRule => SymbolKind::PROPERTY,
+    }
+}
+
+fn document_symbol(
+    item: &OutlineItem,
+    text: &Rope,
+    encoding: PositionEncoding,
+) -> Option<DocumentSymbol> {
+    let children = item
+        .children
+        .iter()
+        .filter_map(|child| document_symbol(child, text, encoding))
+        .collect();
+    // `deprecated` has to be set until it is removed from the protocol
+    #[allow(deprecated)]
+    Some(DocumentSymbol {
+        name: item.name.clone(),
+        detail: item.detail.clone(),
+        kind: symbol_kind(item.kind),
+        tags: None,
+        deprecated: None,
+        range: offsets_to_range(text, &item.range, encoding)?,
+        selection_range: offsets_to_range(text, &item.selection, encoding)?,
+        children: Some(children),
+    })
+}
+
+/// Appends an entry and everything below it to a flat list.
+pub(crate) fn flatten(
+    item: &OutlineItem,
+    container: Option<&str>,
+    uri: &Url,
+    text: &Rope,
+    encoding: PositionEncoding,
+    symbols: &mut Vec<SymbolInformation>,
+) {
+    if let Some(range) = offsets_to_range(text, &item.selection, encoding) {
+        #[allow(deprecated)]
+        symbols.push(SymbolInformation {
+            name: item.name.clone(),
+            kind: symbol_kind(item.kind),
+            tags: None,
+            deprecated: None,
+            location: Location::new(uri.clone(), range),
+            container_name: container.map(str::to_owned),
+        });
+    }
+    for child in &item.children {
+        flatten(child, Some(&item.name), uri, text, encoding, symbols);
+    }
+}
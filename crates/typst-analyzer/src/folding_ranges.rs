@@ -0,0 +1,85 @@
+//! Folding ranges, computed from the syntax tree.
+//!
+//! Most clients only fold whole lines. Ranges that start and end on the same line are dropped,
+//! and of the ranges starting on the same line only the outermost one is kept, as a client can
+//! only fold one of them.
+
+use tower_lsp::lsp_types::{FoldingRange, FoldingRangeKind, FoldingRangeParams};
+use typst_analyzer_analysis::folding::{folding_ranges, FoldKind};
+
+use crate::backend::Backend;
+use crate::position::offset_to_position;
+use crate::prelude::*;
+
+pub(crate) trait HandleFoldingRanges {
+    fn provide_folding_ranges(
+        &self,
+        params: FoldingRangeParams,
+    ) -> Result<Vec<FoldingRange>, Error>;
+}
+
+impl HandleFoldingRanges for Backend {
+    fn provide_folding_ranges(
+        &self,
+        params: FoldingRangeParams,
+    ) -> Result<Vec<FoldingRange>, Error> {
+        let uri = params.text_document.uri;
+        self.with_document(&uri, |doc| {
+            let encoding = self.position_encoding();
+            let capabilities = self
+                .client_capabilities()
+                .text_document
+                .and_then(|text_document| text_document.folding_range);
+            let line_folding_only = capabilities
+                .as_ref()
+                .and_then(|folding| folding.line_folding_only)
+                .unwrap_or(false);
+            let limit = capabilities
+                .and_then(|folding| folding.range_limit)
+                .map_or(usize::MAX, |limit| limit as usize);
+
+            let mut ranges: Vec<FoldingRange> = Vec::new();
+            for fold in folding_ranges(doc.source.root()) {
+                let (Some(start), Some(end)) = (
+                    offset_to_position(&doc.text, fold.range.start, encoding),
+                    offset_to_position(&doc.text, fold.range.end, encoding),
+                ) else {
+                    continue;
+                };
+                if start.line >= end.line
+                    || ranges
+                        .last()
+                        .is_some_and(|last| last.start_line == start.line)
+                {
+                    continue;
+                }
+                ranges.push(FoldingRange {
+                    start_line: start.line,
+                    start_character: (!line_folding_only).then_some(start.character),
+                    end_line: end.line,
+                    end_character: (!line_folding_only).then_some(end.character),
+                    kind: Some(folding_range_kind(fold.kind)),
+                    collapsed_text: None,
+                });
+                if ranges.len() >= limit {
+                    break;
+                }
+            }
+            Ok(ranges)
+        })
+        .ok_or(anyhow!("document is not open"))?
+    }
+}
+
+fn folding_range_kind(kind: FoldKind) -> FoldingRangeKind {
+    match kind {
+        FoldKind::Comment => FoldingRangeKind::Comment,
+        FoldKind::Section
+        | FoldKind::Block
+        | FoldKind::Arguments
+        | FoldKind::List
+        | FoldKind::Raw
+        | FoldKind::Equation
+        | FoldKind::Region => FoldingRangeKind::Region,
+    }
+}
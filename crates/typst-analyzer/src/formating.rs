@@ -1,26 +1,26 @@
 use tower_lsp::lsp_types::{Position, Range, TextEdit, Url};
 
 use crate::backend::Backend;
-use crate::prelude::OkSome;
+use crate::prelude::{offset_to_position, OkSome};
 
 impl Backend {
     pub fn handle_formatting(&self, uri: Url) -> OkSome<Vec<TextEdit>> {
         let mut textedit = Vec::new();
         if let Some(ctx) = self.format_text_document(uri.clone()) {
-            if let Some(text_document) = self.doc_map.get(&uri.to_string()) {
-                let lines: Vec<&str> = text_document.lines().collect();
+            if let Some(doc) = self.documents.get(&uri) {
+                let text_document = &doc.text;
                 let start = Position {
                     line: 0,
                     character: 0,
                 };
-                let end = if let Some(last_line) = lines.last() {
-                    Position {
-                        line: (lines.len() - 1) as u32,
-                        character: last_line.chars().count() as u32,
-                    }
-                } else {
-                    start // If the document is empty, the range is just the start position
-                };
+                // The range spans the whole document, the end position is right after the last
+                // character so that a trailing line break is replaced too.
+                let end = offset_to_position(
+                    text_document,
+                    text_document.len_bytes(),
+                    self.position_encoding(),
+                )
+                .unwrap_or(start);
                 let range = Range { start, end };
                 textedit.push(TextEdit {
                     range,
@@ -32,13 +32,12 @@ impl Backend {
     }
 
     pub fn format_text_document(&self, uri: Url) -> Option<String> {
-        let binding = self.ast_map.get(&uri.to_string());
-        // -- TODO: change default config and provide config file to user
-        let config = typstyle_core::Config::default();
+        let binding = self.documents.get(&uri);
+        let config = self.settings_for(&uri).formatter.typstyle_config();
         let formatter = typstyle_core::Typstyle::new(config);
 
-        if let Some(ast) = &binding {
-            let source = ast.value();
+        if let Some(doc) = &binding {
+            let source = &doc.source;
             if let Ok(formatted) = formatter.format_source(source) {
                 return Some(formatted);
             }
@@ -24,15 +24,16 @@ impl Backend {
     pub fn inlay_hints(&self, uri: Url) -> Result<Vec<InlayHint>, anyhow::Error> {
         let mut hints = Vec::new();
         let mut inlayhints = Vec::new();
-        let binding = self.ast_map.get(&uri.to_string());
+        let enabled = self.settings_for(&uri).inlay_hints;
+        let binding = self.documents.get(&uri);
 
-        if let Some(ast) = &binding {
-            let source = ast.value();
-            for node in ast.root().children() {
-                if node.kind() == SyntaxKind::Linebreak {
-                    // slice out the range of node from ast_map source
+        if let Some(doc) = &binding {
+            let (source, text) = (&doc.source, &doc.text);
+            for node in descendants(source.root()) {
+                if enabled.linebreaks && node.kind() == SyntaxKind::Linebreak {
+                    // slice out the range of node from the source
                     if let Some(range) = &source.range(node.span()) {
-                        let loc = range_to_lsp_range(source, range)?;
+                        let loc = range_to_lsp_range(text, range, self.position_encoding())?;
 
                         hints.push(HintMaker {
                             label: "linebreak",
@@ -43,9 +44,9 @@ impl Backend {
                         });
                     }
                 }
-                if node.kind() == SyntaxKind::Label {
+                if enabled.labels && node.kind() == SyntaxKind::Label {
                     if let Some(range) = &source.range(node.span()) {
-                        let loc = range_to_lsp_range(source, range)?;
+                        let loc = range_to_lsp_range(text, range, self.position_encoding())?;
 
                         hints.push(HintMaker {
                             label: "label",
@@ -56,9 +57,9 @@ impl Backend {
                         });
                     }
                 }
-                if node.kind() == SyntaxKind::Ref {
+                if enabled.references && node.kind() == SyntaxKind::Ref {
                     if let Some(range) = &source.range(node.span()) {
-                        let loc = range_to_lsp_range(source, range)?;
+                        let loc = range_to_lsp_range(text, range, self.position_encoding())?;
 
                         hints.push(HintMaker {
                             label: "reference",
@@ -5,8 +5,9 @@ use tower_lsp::lsp_types::{Hover, HoverContents, HoverParams, MarkupContent, Mar
 use typst_analyzer_analysis::node::kind_walker;
 use typst_syntax::SyntaxKind;
 
-use crate::backend::{position_to_offset, Backend};
+use crate::backend::Backend;
 use crate::error_ctx::TypError;
+use crate::position::position_to_offset;
 use crate::typ_logger;
 
 pub(crate) trait HandleHover {
@@ -17,39 +18,53 @@ impl HandleHover for Backend {
     fn provide_hover_ctx(&self, params: HoverParams) -> Result<Hover, Error> {
         let mut hover_ctx = String::new();
         let uri = params.text_document_position_params.text_document.uri;
-        if let Some(text) = self.doc_map.get(&uri.to_string()) {
-            if let Some(position) =
-                position_to_offset(&text, params.text_document_position_params.position)
-            {
-                if let Some(ast_map_ctx) = self.ast_map.get(&uri.to_string()) {
-                    let syntaxnode = &ast_map_ctx.value().text();
-                    let parsed = typst_syntax::parse(syntaxnode);
-                    // node_walker will walk throug the AST map from cursor position and return
-                    // VecDeque as [Markup, Ref, RefMarker] if cursor is in
-                    // a RefMarker ie, reference.
-                    let syntax_kind: VecDeque<SyntaxKind> = kind_walker(position, &parsed);
-                    typ_logger!("hovered syntax_kind: {:?}", syntax_kind);
-                    let refmarker = syntax_kind.back();
-                    if let Some(syntax) = refmarker {
-                        if *syntax == SyntaxKind::RefMarker {
-                            hover_ctx = REF_DETAILS.to_owned();
-                        }
-                        if *syntax == SyntaxKind::Label {
-                            hover_ctx = LABEL_DETAILS.to_owned();
-                        }
+        if let Some(definition) =
+            self.definition_at(&uri, params.text_document_position_params.position)
+        {
+            let mut value = format!("```typst\n{}\n```", definition.signature);
+            if let Some(docs) = definition.docs {
+                value.push_str("\n\n");
+                value.push_str(&docs);
+            }
+            return Ok(Hover {
+                contents: HoverContents::Markup(MarkupContent {
+                    kind: MarkupKind::Markdown,
+                    value,
+                }),
+                range: None,
+            });
+        }
+        if let Some(doc) = self.documents.get(&uri) {
+            if let Some(position) = position_to_offset(
+                &doc.text,
+                params.text_document_position_params.position,
+                self.position_encoding(),
+            ) {
+                // node_walker will walk throug the AST map from cursor position and return
+                // VecDeque as [Markup, Ref, RefMarker] if cursor is in
+                // a RefMarker ie, reference.
+                let syntax_kind: VecDeque<SyntaxKind> = kind_walker(position, doc.source.root());
+                typ_logger!("hovered syntax_kind: {:?}", syntax_kind);
+                let refmarker = syntax_kind.back();
+                if let Some(syntax) = refmarker {
+                    if *syntax == SyntaxKind::RefMarker {
+                        hover_ctx = REF_DETAILS.to_owned();
+                    }
+                    if *syntax == SyntaxKind::Label {
+                        hover_ctx = LABEL_DETAILS.to_owned();
                     }
-                    // refmarker = RefMarker
-                    typ_logger!("hovered : {:?}", refmarker);
-                    typ_logger!("hovered ctx: {:?}", &hover_ctx);
-                    // dummy return
-                    return Ok(Hover {
-                        contents: HoverContents::Markup(MarkupContent {
-                            kind: MarkupKind::Markdown,
-                            value: hover_ctx,
-                        }),
-                        range: None,
-                    });
                 }
+                // refmarker = RefMarker
+                typ_logger!("hovered : {:?}", refmarker);
+                typ_logger!("hovered ctx: {:?}", &hover_ctx);
+                // dummy return
+                return Ok(Hover {
+                    contents: HoverContents::Markup(MarkupContent {
+                        kind: MarkupKind::Markdown,
+                        value: hover_ctx,
+                    }),
+                    range: None,
+                });
             }
         }
         Err(TypError::Invalid.into())
@@ -1,12 +1,22 @@
 pub mod backend;
 pub(crate) mod code_actions;
 pub(crate) mod completion;
+pub mod config;
 pub(crate) mod definition;
 mod diagnostics;
+pub mod document;
+pub(crate) mod document_symbols;
 pub mod error_ctx;
+pub(crate) mod folding_ranges;
 pub mod formating;
 pub mod hints;
 pub(crate) mod hover;
+pub mod position;
 pub mod prelude;
+pub(crate) mod references;
+pub(crate) mod rename;
+pub(crate) mod selection_ranges;
+pub mod semantic_tokens;
 mod symbols;
-pub(crate) mod workspace;
+pub mod workspace;
+pub mod workspace_symbols;
@@ -1,8 +1,6 @@
-use std::sync::Arc;
-
-use dashmap::DashMap;
 use tower_lsp::{LspService, Server};
 use typst_analyzer::backend::Backend;
+use typst_analyzer::workspace_symbols::WorkspaceSymbolService;
 
 #[tokio::main]
 async fn main() {
@@ -10,11 +8,8 @@ async fn main() {
 
     let (stdin, stdout) = (tokio::io::stdin(), tokio::io::stdout());
 
-    let (service, socket) = LspService::new(|client| Backend {
-        client,
-        doc_map: DashMap::new(),
-        ast_map: DashMap::new(),
-        symbol_table: Arc::new(DashMap::new()),
-    });
-    Server::new(stdin, stdout, socket).serve(service).await;
+    let (service, socket) = LspService::new(Backend::new);
+    Server::new(stdin, stdout, socket)
+        .serve(WorkspaceSymbolService::new(service))
+        .await;
 }
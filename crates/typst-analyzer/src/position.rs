@@ -0,0 +1,264 @@
+//! Conversions between LSP positions and byte offsets into a document.
+//!
+//! Documents are kept as a [`Rope`] so that line lookups stay cheap on large files. LSP positions
+//! count `character` in code units of the negotiated [`PositionEncoding`], while typst-syntax works
+//! with byte offsets, so every position coming from or going to the client has to go through this
+//! module.
+//!
+//! Line breaks are `\n`, `\r\n` and `\r`, as defined by the LSP specification.
+
+use ropey::Rope;
+use tower_lsp::lsp_types::{Position, PositionEncodingKind, Range};
+
+/// The unit in which the `character` of an LSP position is counted.
+///
+/// Negotiated with the client during `initialize`. UTF-16 is the default every client must
+/// support, UTF-8 is preferred when offered since it maps directly onto byte offsets.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PositionEncoding {
+    Utf8,
+    #[default]
+    Utf16,
+    Utf32,
+}
+
+impl PositionEncoding {
+    /// Picks the encoding to use from the encodings offered by the client.
+    pub fn negotiate(offered: Option<&[PositionEncodingKind]>) -> Self {
+        let offered = offered.unwrap_or_default();
+        if offered.contains(&PositionEncodingKind::UTF8) {
+            return PositionEncoding::Utf8;
+        }
+        offered
+            .iter()
+            .find_map(PositionEncoding::from_kind)
+            .unwrap_or_default()
+    }
+
+    fn from_kind(kind: &PositionEncodingKind) -> Option<Self> {
+        match kind.as_str() {
+            "utf-8" => Some(PositionEncoding::Utf8),
+            "utf-16" => Some(PositionEncoding::Utf16),
+            "utf-32" => Some(PositionEncoding::Utf32),
+            _ => None,
+        }
+    }
+
+    /// The kind reported back to the client in `ServerCapabilities.position_encoding`.
+    pub fn kind(self) -> PositionEncodingKind {
+        match self {
+            PositionEncoding::Utf8 => PositionEncodingKind::UTF8,
+            PositionEncoding::Utf16 => PositionEncodingKind::UTF16,
+            PositionEncoding::Utf32 => PositionEncodingKind::UTF32,
+        }
+    }
+
+    /// Number of code units before the char at `char_idx`.
+    fn char_to_unit(self, rope: &Rope, char_idx: usize) -> usize {
+        match self {
+            PositionEncoding::Utf8 => rope.char_to_byte(char_idx),
+            PositionEncoding::Utf16 => rope.char_to_utf16_cu(char_idx),
+            PositionEncoding::Utf32 => char_idx,
+        }
+    }
+
+    /// Index of the char containing the code unit at `unit_idx`.
+    fn unit_to_char(self, rope: &Rope, unit_idx: usize) -> Option<usize> {
+        match self {
+            PositionEncoding::Utf8 => rope.try_byte_to_char(unit_idx).ok(),
+            PositionEncoding::Utf16 => rope.try_utf16_cu_to_char(unit_idx).ok(),
+            PositionEncoding::Utf32 => (unit_idx <= rope.len_chars()).then_some(unit_idx),
+        }
+    }
+}
+
+/// Converts an LSP position into a byte offset in the rope.
+///
+/// A `character` past the end of the line is clamped to the end of the line (excluding the line
+/// break), as required by the specification. Returns `None` if the line does not exist.
+pub fn position_to_offset(
+    rope: &Rope,
+    position: Position,
+    encoding: PositionEncoding,
+) -> Option<usize> {
+    let line_idx = position.line as usize;
+    if line_idx >= rope.len_lines() {
+        return None;
+    }
+    let line_start = rope.try_line_to_char(line_idx).ok()?;
+    let line_end = line_start + line_len_without_break(rope, line_idx);
+
+    let start_unit = encoding.char_to_unit(rope, line_start);
+    let end_unit = encoding.char_to_unit(rope, line_end);
+    let target_unit = (start_unit + position.character as usize).min(end_unit);
+
+    let char_idx = encoding.unit_to_char(rope, target_unit)?;
+    rope.try_char_to_byte(char_idx).ok()
+}
+
+/// Converts a byte offset in the rope into an LSP position.
+///
+/// Returns `None` if the offset is past the end of the document.
+pub fn offset_to_position(
+    rope: &Rope,
+    offset: usize,
+    encoding: PositionEncoding,
+) -> Option<Position> {
+    let char_idx = rope.try_byte_to_char(offset).ok()?;
+    let line_idx = rope.try_char_to_line(char_idx).ok()?;
+    let line_start = rope.try_line_to_char(line_idx).ok()?;
+    let character = encoding.char_to_unit(rope, char_idx) - encoding.char_to_unit(rope, line_start);
+    Some(Position {
+        line: line_idx as u32,
+        character: character as u32,
+    })
+}
+
+/// Converts an LSP range into a byte range in the rope.
+pub fn range_to_offsets(
+    rope: &Rope,
+    range: Range,
+    encoding: PositionEncoding,
+) -> Option<core::ops::Range<usize>> {
+    let start = position_to_offset(rope, range.start, encoding)?;
+    let end = position_to_offset(rope, range.end, encoding)?;
+    Some(start..end.max(start))
+}
+
+/// Converts a byte range in the rope into an LSP range.
+pub fn offsets_to_range(
+    rope: &Rope,
+    range: &core::ops::Range<usize>,
+    encoding: PositionEncoding,
+) -> Option<Range> {
+    Some(Range {
+        start: offset_to_position(rope, range.start, encoding)?,
+        end: offset_to_position(rope, range.end, encoding)?,
+    })
+}
+
+/// Replaces the text between two LSP positions, or the whole document if `range` is `None`.
+///
+/// Returns the replaced byte range of the old text.
+pub fn apply_change(
+    rope: &mut Rope,
+    range: Option<Range>,
+    text: &str,
+    encoding: PositionEncoding,
+) -> Option<core::ops::Range<usize>> {
+    let replaced = match range {
+        Some(range) => range_to_offsets(rope, range, encoding)?,
+        None => 0..rope.len_bytes(),
+    };
+    let start = rope.try_byte_to_char(replaced.start).ok()?;
+    let end = rope.try_byte_to_char(replaced.end).ok()?;
+    rope.try_remove(start..end).ok()?;
+    rope.try_insert(start, text).ok()?;
+    Some(replaced)
+}
+
+/// Number of chars on a line, not counting its trailing line break.
+fn line_len_without_break(rope: &Rope, line_idx: usize) -> usize {
+    let line = rope.line(line_idx);
+    let len = line.len_chars();
+    match (
+        len.checked_sub(2).map(|i| line.char(i)),
+        len.checked_sub(1).map(|i| line.char(i)),
+    ) {
+        (Some('\r'), Some('\n')) => len - 2,
+        (_, Some('\n' | '\r')) => len - 1,
+        _ => len,
+    }
+}
+
+#[test]
+fn position_roundtrip_test() {
+    // "Ü" is two bytes and one UTF-16 unit, "😀" is four bytes and two UTF-16 units.
+    let rope = Rope::from_str("= Über\r\n😀 @ref\rlast");
+    let at_ref = Position {
+        line: 1,
+        character: 3,
+    };
+    let offset = "= Über\r\n😀 ".len();
+    assert_eq!(
+        position_to_offset(&rope, at_ref, PositionEncoding::Utf16),
+        Some(offset)
+    );
+    assert_eq!(
+        offset_to_position(&rope, offset, PositionEncoding::Utf16),
+        Some(at_ref)
+    );
+
+    // Positions past the end of a line are clamped before the line break.
+    let past_end = Position {
+        line: 0,
+        character: 42,
+    };
+    assert_eq!(
+        position_to_offset(&rope, past_end, PositionEncoding::Utf16),
+        Some("= Über".len())
+    );
+    assert_eq!(
+        position_to_offset(
+            &rope,
+            Position {
+                line: 2,
+                character: 0
+            },
+            PositionEncoding::Utf16
+        ),
+        Some("= Über\r\n😀 @ref\r".len())
+    );
+    assert_eq!(
+        position_to_offset(
+            &rope,
+            Position {
+                line: 3,
+                character: 0
+            },
+            PositionEncoding::Utf16
+        ),
+        None
+    );
+}
+
+#[test]
+fn position_encoding_test() {
+    let rope = Rope::from_str("😀 @ref");
+    let offset = "😀 ".len();
+    for (encoding, character) in [
+        (PositionEncoding::Utf8, 5),
+        (PositionEncoding::Utf16, 3),
+        (PositionEncoding::Utf32, 2),
+    ] {
+        let position = Position { line: 0, character };
+        assert_eq!(position_to_offset(&rope, position, encoding), Some(offset));
+        assert_eq!(offset_to_position(&rope, offset, encoding), Some(position));
+    }
+    assert_eq!(
+        PositionEncoding::negotiate(Some(&[
+            PositionEncodingKind::UTF16,
+            PositionEncodingKind::UTF8
+        ])),
+        PositionEncoding::Utf8
+    );
+    assert_eq!(PositionEncoding::negotiate(None), PositionEncoding::Utf16);
+}
+
+#[test]
+fn line_breaks_test() {
+    // Form feed, NEL and the line separator are no line breaks for the client.
+    let rope = Rope::from_str("a\u{c}b\u{85}c\u{2028}d\nlast");
+    assert_eq!(rope.len_lines(), 2);
+    assert_eq!(
+        offset_to_position(
+            &rope,
+            "a\u{c}b\u{85}c\u{2028}d\n".len(),
+            PositionEncoding::Utf16
+        ),
+        Some(Position {
+            line: 1,
+            character: 0
+        })
+    );
+}
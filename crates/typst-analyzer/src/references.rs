@@ -0,0 +1,245 @@
+//! Find all references of a label or a binding.
+//!
+//! Label references are read from the symbol index: `@label`, `ref(<label>)`, `show <label>: ..`
+//! and `label("label")`, in every file of the documents the file is part of. References of a
+//! binding are the identifiers resolving to it, searched in its file and in every file importing
+//! it, directly or through other files.
+
+use std::collections::{HashSet, VecDeque};
+
+use tower_lsp::lsp_types::{Location, Position, ReferenceParams, Url};
+use typst_analyzer_analysis::definition::{BindingKind, Scopes};
+use typst_syntax::{ast, LinkedNode, SyntaxKind};
+
+use crate::backend::Backend;
+use crate::document::Document;
+use crate::position::offsets_to_range;
+use crate::prelude::*;
+use crate::symbols::Symbol;
+use crate::workspace::modules::Definition;
+use crate::workspace::symbols::{DependencyKind, SymbolKind, SymbolRole};
+
+pub(crate) trait HandleReferences {
+    fn provide_references(&self, params: ReferenceParams) -> Result<Vec<Location>, Error>;
+}
+
+impl HandleReferences for Backend {
+    fn provide_references(&self, params: ReferenceParams) -> Result<Vec<Location>, Error> {
+        let uri = params.text_document_position.text_document.uri;
+        let position = params.text_document_position.position;
+        let include_declaration = params.context.include_declaration;
+
+        if let Some(name) = self.label_at(&uri, position) {
+            return Ok(self.label_references(&uri, &name, include_declaration));
+        }
+        let definition = self
+            .definition_at(&uri, position)
+            .ok_or(anyhow!("no label or binding at the position"))?;
+        Ok(self.binding_references(&definition, include_declaration))
+    }
+}
+
+impl Backend {
+    /// The name of the label or label reference at a position.
+    pub(crate) fn label_at(&self, uri: &Url, position: Position) -> Option<String> {
+        self.symbol_index
+            .with_file(uri, |file_symbols| {
+                [SymbolRole::Definition, SymbolRole::Reference]
+                    .into_iter()
+                    .flat_map(|role| file_symbols.all(SymbolKind::Label, role))
+                    .find(|symbol| {
+                        let range = symbol.location.range;
+                        range.start <= position && position <= range.end
+                    })
+                    .map(|symbol| symbol.name.clone())
+            })
+            .flatten()
+    }
+
+    /// The labels and references with a name, in the documents a file is part of.
+    pub(crate) fn label_symbols(
+        &self,
+        uri: &Url,
+        name: &str,
+        include_declaration: bool,
+    ) -> Vec<Symbol> {
+        let roles = match include_declaration {
+            true => vec![SymbolRole::Definition, SymbolRole::Reference],
+            false => vec![SymbolRole::Reference],
+        };
+        let mut symbols = Vec::new();
+        for file in self.document_files(uri) {
+            self.symbol_index.with_file(&file, |file_symbols| {
+                for role in &roles {
+                    symbols.extend_from_slice(file_symbols.get(SymbolKind::Label, *role, name));
+                }
+            });
+        }
+        symbols
+    }
+
+    fn label_references(&self, uri: &Url, name: &str, include_declaration: bool) -> Vec<Location> {
+        self.label_symbols(uri, name, include_declaration)
+            .into_iter()
+            .map(|symbol| symbol.location)
+            .collect()
+    }
+
+    /// The identifiers resolving to a binding, the binding itself included if asked for.
+    pub(crate) fn binding_references(
+        &self,
+        definition: &Definition,
+        include_declaration: bool,
+    ) -> Vec<Location> {
+        let mut references = Vec::new();
+        self.visit_binding_references(definition, |file, doc, node| {
+            let Some(range) = offsets_to_range(&doc.text, &node.range(), self.position_encoding())
+            else {
+                return;
+            };
+            let location = Location::new(file.clone(), range);
+            if include_declaration || location != definition.location {
+                references.push(location);
+            }
+        });
+        references
+    }
+
+    /// Calls `visit` with every identifier resolving to a binding, the binding itself included.
+    pub(crate) fn visit_binding_references(
+        &self,
+        definition: &Definition,
+        mut visit: impl FnMut(&Url, &Document, &LinkedNode),
+    ) {
+        let defined_in = &definition.location.uri;
+        // Parameters and loop variables are not visible outside their file
+        let files = match definition.kind {
+            BindingKind::Parameter | BindingKind::LoopVariable => vec![defined_in.clone()],
+            _ => self.importers(defined_in),
+        };
+        for file in files {
+            self.with_document(&file, |doc| {
+                // The bindings of the blocks of the file are collected once, not per identifier
+                let mut scopes = Scopes::default();
+                let names = local_names(doc.source.root(), &definition.name);
+                for node in descendants(doc.source.root()) {
+                    if !matches!(node.kind(), SyntaxKind::Ident | SyntaxKind::MathIdent)
+                        || !names.contains(node.text().as_str())
+                    {
+                        continue;
+                    }
+                    if self
+                        .definition_in(&file, doc, &node, &mut scopes)
+                        .is_some_and(|found| found.location == definition.location)
+                    {
+                        visit(&file, doc, &node);
+                    }
+                }
+            });
+        }
+    }
+
+    /// A file and every file importing it, directly or through other files.
+    fn importers(&self, uri: &Url) -> Vec<Url> {
+        let mut files = vec![uri.clone()];
+        let mut visited = HashSet::from([uri.clone()]);
+        let mut queue = VecDeque::from([uri.clone()]);
+        while let Some(file) = queue.pop_front() {
+            for importer in self.symbol_index.dependents(&file, DependencyKind::Import) {
+                if visited.insert(importer.clone()) {
+                    files.push(importer.clone());
+                    queue.push_back(importer);
+                }
+            }
+        }
+        files
+    }
+}
+
+/// The names a binding may go by in a file, its own name and the names it is imported as with
+/// `import "file.typ": name as other`.
+fn local_names(root: &typst_syntax::SyntaxNode, name: &str) -> HashSet<String> {
+    let mut names = HashSet::from([name.to_owned()]);
+    for node in descendants(root) {
+        if let Some(item) = node.cast::<ast::RenamedImportItem>() {
+            if item.original_name().as_str() == name {
+                names.insert(item.new_name().as_str().to_owned());
+            }
+        }
+    }
+    names
+}
+
+#[tokio::test]
+async fn binding_references_test() {
+    use crate::workspace::fs::temp_workspace;
+
+    let root = temp_workspace(
+        "binding-references",
+        &[
+            ("lib.typ", "#let helper(x) = x + 1\n#let value = helper(1)"),
+            (
+                "main.typ",
+                "#import \"lib.typ\": helper\n#let value = 2\n#helper(value)\n\
+                 #let g(helper) = helper\n#{ let helper = 3; helper }",
+            ),
+            ("other.typ", "#import \"lib.typ\" as l\n#l.helper(2)"),
+        ],
+    );
+    let folder = Url::from_directory_path(&root);
+    assert!(folder.is_ok());
+    let Ok(folder) = folder else { return };
+    let backend = Backend::detached();
+    backend.add_workspace_folder(folder.clone());
+    backend.index_workspace_folders(vec![folder]).await;
+    let references = |file: &str, line: u32, character: u32, include_declaration: bool| {
+        let Ok(uri) = Url::from_file_path(root.join(file)) else {
+            return Vec::new();
+        };
+        let Some(definition) = backend.definition_at(&uri, Position::new(line, character)) else {
+            return Vec::new();
+        };
+        let mut found: Vec<(String, u32, u32)> = backend
+            .binding_references(&definition, include_declaration)
+            .into_iter()
+            .map(|location| {
+                let name = location.uri.path().rsplit('/').next().unwrap_or_default();
+                let start = location.range.start;
+                (name.to_owned(), start.line, start.character)
+            })
+            .collect();
+        found.sort();
+        found
+    };
+    let at = |file: &str, line: u32, character: u32| (file.to_owned(), line, character);
+
+    // Imports and module fields in other files, not the parameter or the block variable shadowing
+    // the import
+    let helper = [
+        at("lib.typ", 0, 5),
+        at("lib.typ", 1, 13),
+        at("main.typ", 0, 19),
+        at("main.typ", 2, 1),
+        at("other.typ", 1, 3),
+    ];
+    assert_eq!(references("lib.typ", 0, 5, true), helper);
+    assert_eq!(references("main.typ", 2, 3, true), helper);
+    assert_eq!(references("lib.typ", 0, 5, false), helper[1..]);
+
+    // A closure parameter is only seen in the closure
+    assert_eq!(
+        references("main.typ", 3, 7, true),
+        [at("main.typ", 3, 7), at("main.typ", 3, 17)]
+    );
+    assert_eq!(references("lib.typ", 0, 17, false), [at("lib.typ", 0, 17)]);
+    assert_eq!(
+        references("main.typ", 4, 19, true),
+        [at("main.typ", 4, 7), at("main.typ", 4, 19)]
+    );
+    // A binding of the same name in another file is another binding
+    assert_eq!(
+        references("main.typ", 1, 5, true),
+        [at("main.typ", 1, 5), at("main.typ", 2, 8)]
+    );
+    let _ = std::fs::remove_dir_all(root);
+}
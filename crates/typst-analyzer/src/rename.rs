@@ -0,0 +1,370 @@
+//! Renaming labels and bindings.
+//!
+//! A label is renamed everywhere it is written in the documents the file is part of: the
+//! `<label>` itself, `@label` references, `<label>` values in code and the strings of
+//! `label("label")` calls. Only the name is replaced, so the supplement of `@label[Chapter]` and
+//! the surrounding call stay as they are.
+//!
+//! A binding is renamed where it is made and at every identifier resolving to it, including the
+//! import lists of other files and, for a named parameter, the named arguments of the calls. A
+//! name imported with `import "x.typ": name as alias` is only renamed in the file it is imported
+//! into when renaming the alias. Renames that would make an identifier resolve to another binding
+//! are refused.
+
+use std::collections::HashMap;
+
+use tower_lsp::lsp_types::{
+    Position, PrepareRenameResponse, Range, RenameParams, TextDocumentPositionParams, TextEdit,
+    Url, WorkspaceEdit,
+};
+use typst_analyzer_analysis::definition::{
+    conflicts, ident_at, parameter_function, resolve, BindingKind,
+};
+use typst_syntax::{is_ident, is_valid_label_literal_id, LinkedNode, SyntaxKind};
+
+use crate::backend::Backend;
+use crate::document::Document;
+use crate::position::offsets_to_range;
+use crate::prelude::*;
+use crate::symbols::Symbol;
+use crate::workspace::modules::Definition;
+use crate::workspace::symbols::{SymbolKind, SymbolRole};
+
+type Changes = HashMap<Url, Vec<TextEdit>>;
+
+pub(crate) trait HandleRename {
+    fn provide_prepare_rename(
+        &self,
+        params: TextDocumentPositionParams,
+    ) -> Result<PrepareRenameResponse, Error>;
+    fn provide_rename(&self, params: RenameParams) -> Result<WorkspaceEdit, Error>;
+}
+
+impl HandleRename for Backend {
+    fn provide_prepare_rename(
+        &self,
+        params: TextDocumentPositionParams,
+    ) -> Result<PrepareRenameResponse, Error> {
+        let uri = &params.text_document.uri;
+        let label = self
+            .symbol_index
+            .with_file(uri, |file_symbols| {
+                [SymbolRole::Definition, SymbolRole::Reference]
+                    .into_iter()
+                    .flat_map(|role| file_symbols.all(SymbolKind::Label, role))
+                    .map(|symbol| (symbol, name_range(symbol)))
+                    .find(|(_, range)| {
+                        range.start <= params.position && params.position <= range.end
+                    })
+                    .map(
+                        |(symbol, range)| PrepareRenameResponse::RangeWithPlaceholder {
+                            range,
+                            placeholder: symbol.name.clone(),
+                        },
+                    )
+            })
+            .ok_or(anyhow!("document is not indexed"))?;
+        if let Some(label) = label {
+            return Ok(label);
+        }
+
+        let definition = self
+            .definition_at(uri, params.position)
+            .ok_or(anyhow!("only labels and bindings can be renamed"))?;
+        if definition.kind == BindingKind::Module {
+            return Err(anyhow!("a module is named after its file"));
+        }
+        let (range, name) = self
+            .with_document(uri, |doc| self.ident_at_position(doc, params.position))
+            .ok_or(anyhow!("document is not open"))?
+            .ok_or(anyhow!("no identifier at the position"))?;
+        Ok(PrepareRenameResponse::RangeWithPlaceholder {
+            range,
+            placeholder: name,
+        })
+    }
+
+    fn provide_rename(&self, params: RenameParams) -> Result<WorkspaceEdit, Error> {
+        let uri = params.text_document_position.text_document.uri;
+        let position = params.text_document_position.position;
+        let Some(name) = self.label_at(&uri, position) else {
+            return self.rename_binding(&uri, position, &params.new_name);
+        };
+        validate_label(&params.new_name)?;
+
+        let mut changes = Changes::new();
+        for symbol in self.label_symbols(&uri, &name, true) {
+            changes
+                .entry(symbol.location.uri.clone())
+                .or_default()
+                .push(TextEdit::new(name_range(&symbol), params.new_name.clone()));
+        }
+        Ok(WorkspaceEdit::new(changes))
+    }
+}
+
+impl Backend {
+    /// The range and text of the identifier at a position.
+    fn ident_at_position(&self, doc: &Document, position: Position) -> Option<(Range, String)> {
+        let offset = position_to_offset(&doc.text, position, self.position_encoding())?;
+        let ident = ident_at(doc.source.root(), offset)?;
+        let range = offsets_to_range(&doc.text, &ident.range(), self.position_encoding())?;
+        Some((range, ident.text().as_str().to_owned()))
+    }
+
+    fn rename_binding(
+        &self,
+        uri: &Url,
+        position: Position,
+        new_name: &str,
+    ) -> Result<WorkspaceEdit, Error> {
+        if !is_ident(new_name) {
+            return Err(anyhow!("`{new_name}` is not a valid identifier"));
+        }
+        let definition = self
+            .definition_at(uri, position)
+            .ok_or(anyhow!("only labels and bindings can be renamed"))?;
+        if definition.kind == BindingKind::Module {
+            return Err(anyhow!("a module is named after its file"));
+        }
+        let (_, old_name) = self
+            .with_document(uri, |doc| self.ident_at_position(doc, position))
+            .ok_or(anyhow!("document is not open"))?
+            .ok_or(anyhow!("no identifier at the position"))?;
+        // An alias of an imported name or module only exists in the importing file
+        let local = old_name != definition.name || definition.kind == BindingKind::ModuleAlias;
+
+        let mut changes = Changes::new();
+        let mut conflict = None;
+        self.visit_binding_references(&definition, |file, doc, node| {
+            if node.text() != old_name.as_str() || (local && file != uri) {
+                return;
+            }
+            if conflicts(node, new_name) {
+                conflict = Some(file.clone());
+            }
+            self.push_edit(&mut changes, file, doc, node, new_name);
+        });
+        if let Some(file) = conflict {
+            return Err(anyhow!(
+                "`{new_name}` is already bound where `{old_name}` is used in {file}"
+            ));
+        }
+        if let Some(function) = self.parameter_function(&definition) {
+            self.rename_named_arguments(&mut changes, &function, &old_name, new_name);
+        }
+        Ok(WorkspaceEdit::new(changes))
+    }
+
+    /// The function a named parameter belongs to.
+    fn parameter_function(&self, parameter: &Definition) -> Option<Definition> {
+        let uri = &parameter.location.uri;
+        self.with_document(uri, |doc| {
+            let offset = position_to_offset(
+                &doc.text,
+                parameter.location.range.start,
+                self.position_encoding(),
+            )?;
+            let binding = resolve(&ident_at(doc.source.root(), offset)?)?;
+            let function = parameter_function(&binding)?;
+            self.definition_of(uri, doc, &function)
+        })
+        .flatten()
+    }
+
+    /// Renames the argument in the calls of a function that passes a named parameter,
+    /// `width` in `f(width: 1cm)`.
+    fn rename_named_arguments(
+        &self,
+        changes: &mut Changes,
+        function: &Definition,
+        old_name: &str,
+        new_name: &str,
+    ) {
+        self.visit_binding_references(function, |file, doc, node| {
+            // `f(..)` or `module.f(..)`
+            let callee = match node.parent_kind() {
+                Some(SyntaxKind::FieldAccess) => node.parent().cloned(),
+                _ => Some(node.clone()),
+            };
+            let Some(call) = callee
+                .filter(|callee| callee.index() == 0)
+                .and_then(|callee| callee.parent().cloned())
+                .filter(|call| call.kind() == SyntaxKind::FuncCall)
+            else {
+                return;
+            };
+            let arguments = call
+                .children()
+                .filter(|child| child.kind() == SyntaxKind::Args)
+                .flat_map(|args| args.children())
+                .filter(|arg| arg.kind() == SyntaxKind::Named)
+                .filter_map(|named| named.children().next())
+                .filter(|name| name.text() == old_name);
+            for name in arguments {
+                self.push_edit(changes, file, doc, &name, new_name);
+            }
+        });
+    }
+
+    fn push_edit(
+        &self,
+        changes: &mut Changes,
+        file: &Url,
+        doc: &Document,
+        node: &LinkedNode,
+        new_name: &str,
+    ) {
+        if let Some(range) = offsets_to_range(&doc.text, &node.range(), self.position_encoding()) {
+            changes
+                .entry(file.clone())
+                .or_default()
+                .push(TextEdit::new(range, new_name.to_owned()));
+        }
+    }
+}
+
+/// Rejects names that do not make a label which can also be referenced with `@name`.
+fn validate_label(name: &str) -> Result<(), Error> {
+    if !is_valid_label_literal_id(name) {
+        return Err(anyhow!("`{name}` is not a valid label name"));
+    }
+    // `@name.` ends the reference before the dot
+    if name.ends_with(['.', ':']) {
+        return Err(anyhow!(
+            "a label name cannot end with `{}`",
+            &name[name.len() - 1..]
+        ));
+    }
+    Ok(())
+}
+
+/// The range of the name in a label symbol, without `<>`, `@` or quotes.
+fn name_range(symbol: &Symbol) -> Range {
+    let mut range = symbol.location.range;
+    match symbol.symbol_type {
+        SyntaxKind::Ref => range.start.character += 1,
+        _ => {
+            range.start.character += 1;
+            range.end.character = range.end.character.saturating_sub(1);
+        }
+    }
+    range
+}
+
+#[test]
+fn validate_label_test() {
+    assert!(validate_label("sec:intro").is_ok());
+    assert!(validate_label("fig.1-a_b").is_ok());
+    assert!(validate_label("").is_err());
+    assert!(validate_label("two words").is_err());
+    assert!(validate_label("intro.").is_err());
+}
+
+#[tokio::test]
+async fn rename_binding_test() {
+    use tower_lsp::lsp_types::TextDocumentIdentifier;
+
+    use crate::workspace::fs::temp_workspace;
+
+    let root = temp_workspace(
+        "rename-binding",
+        &[
+            (
+                "lib.typ",
+                "#let frame(width: 1cm, body) = box(width: width, body)\n\
+                 #let note = frame(width: 2cm)[x]",
+            ),
+            (
+                "main.typ",
+                "#import \"lib.typ\": frame, note as remark\n#frame(width: 3cm)[a]\n\
+                 #let g(frame) = frame\n#remark",
+            ),
+            (
+                "other.typ",
+                "#import \"lib.typ\" as l\n#l.frame(width: 1cm)[b]",
+            ),
+        ],
+    );
+    let folder = Url::from_directory_path(&root);
+    assert!(folder.is_ok());
+    let Ok(folder) = folder else { return };
+    let backend = Backend::detached();
+    backend.add_workspace_folder(folder.clone());
+    backend.index_workspace_folders(vec![folder]).await;
+    let rename = |file: &str, line: u32, character: u32, new_name: &str| {
+        let uri = Url::from_file_path(root.join(file)).map_err(|_| anyhow!("invalid path"))?;
+        let edit = backend.provide_rename(RenameParams {
+            text_document_position: TextDocumentPositionParams::new(
+                TextDocumentIdentifier::new(uri),
+                Position::new(line, character),
+            ),
+            new_name: new_name.to_owned(),
+            work_done_progress_params: Default::default(),
+        })?;
+        let mut edits: Vec<(String, u32, u32)> = edit
+            .changes
+            .into_iter()
+            .flatten()
+            .flat_map(|(uri, edits)| {
+                let name = uri.path().rsplit('/').next().unwrap_or_default().to_owned();
+                edits
+                    .into_iter()
+                    .filter(|edit| edit.new_text == new_name)
+                    .map(move |edit| {
+                        (
+                            name.clone(),
+                            edit.range.start.line,
+                            edit.range.start.character,
+                        )
+                    })
+            })
+            .collect();
+        edits.sort();
+        Ok::<_, Error>(edits)
+    };
+    let at = |file: &str, line: u32, character: u32| (file.to_owned(), line, character);
+
+    // The import list of another file is rewritten, the parameter shadowing the name is not
+    let renamed = rename("lib.typ", 0, 5, "panel");
+    assert!(renamed.is_ok());
+    let Ok(renamed) = renamed else { return };
+    assert_eq!(
+        renamed,
+        [
+            at("lib.typ", 0, 5),
+            at("lib.typ", 1, 12),
+            at("main.typ", 0, 19),
+            at("main.typ", 1, 1),
+            at("other.typ", 1, 3),
+        ]
+    );
+    let renamed = rename("main.typ", 2, 16, "frame2");
+    assert!(renamed.is_ok_and(|edits| edits == [at("main.typ", 2, 7), at("main.typ", 2, 16)]));
+
+    // A name that is already bound where the binding is used is refused, in any file
+    assert!(rename("lib.typ", 1, 5, "frame").is_err());
+    assert!(rename("lib.typ", 0, 5, "remark").is_err());
+
+    // An alias is renamed in its file only, the imported name keeps the alias alone
+    let renamed = rename("main.typ", 3, 1, "aside");
+    assert!(renamed.is_ok_and(|edits| edits == [at("main.typ", 0, 34), at("main.typ", 3, 1)]));
+    let renamed = rename("lib.typ", 1, 5, "memo");
+    assert!(renamed.is_ok_and(|edits| edits == [at("lib.typ", 1, 5), at("main.typ", 0, 26)]));
+
+    // Renaming a named parameter renames the arguments of the calls, not those of other functions
+    let renamed = rename("lib.typ", 0, 11, "size");
+    assert!(renamed.is_ok());
+    let Ok(renamed) = renamed else { return };
+    assert_eq!(
+        renamed,
+        [
+            at("lib.typ", 0, 11),
+            at("lib.typ", 0, 42),
+            at("lib.typ", 1, 18),
+            at("main.typ", 1, 7),
+            at("other.typ", 1, 9),
+        ]
+    );
+    let _ = std::fs::remove_dir_all(root);
+}
@@ -0,0 +1,54 @@
+//! Expanding and shrinking the selection, one syntax node at a time.
+
+use tower_lsp::lsp_types::{SelectionRange, SelectionRangeParams};
+use typst_analyzer_analysis::selection::selection_ranges;
+
+use crate::backend::Backend;
+use crate::position::{offsets_to_range, position_to_offset};
+use crate::prelude::*;
+
+pub(crate) trait HandleSelectionRanges {
+    fn provide_selection_ranges(
+        &self,
+        params: SelectionRangeParams,
+    ) -> Result<Vec<SelectionRange>, Error>;
+}
+
+impl HandleSelectionRanges for Backend {
+    // One selection range per position, in the same order. The response cannot leave a position
+    // out, a position outside of the document gets an empty range.
+    fn provide_selection_ranges(
+        &self,
+        params: SelectionRangeParams,
+    ) -> Result<Vec<SelectionRange>, Error> {
+        let uri = params.text_document.uri;
+        self.with_document(&uri, |doc| {
+            let encoding = self.position_encoding();
+            let root = doc.source.root();
+
+            let mut selections = Vec::new();
+            for position in params.positions {
+                let ranges = position_to_offset(&doc.text, position, encoding)
+                    .map(|offset| selection_ranges(root, offset))
+                    .unwrap_or_default();
+                // Nested from the outside in, the innermost range is the one returned
+                let mut selection: Option<SelectionRange> = None;
+                for range in ranges.iter().rev() {
+                    let Some(range) = offsets_to_range(&doc.text, range, encoding) else {
+                        continue;
+                    };
+                    selection = Some(SelectionRange {
+                        range,
+                        parent: selection.map(Box::new),
+                    });
+                }
+                selections.push(selection.unwrap_or(SelectionRange {
+                    range: tower_lsp::lsp_types::Range::new(position, position),
+                    parent: None,
+                }));
+            }
+            Ok(selections)
+        })
+        .ok_or(anyhow!("document is not open"))?
+    }
+}
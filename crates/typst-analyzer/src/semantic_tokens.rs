@@ -0,0 +1,254 @@
+//! Semantic tokens, highlighting from the syntax tree.
+//!
+//! The tokens of a document are sent whole, for a range, or as the difference to the tokens
+//! sent last for the document. The difference of an edit is usually a few tokens, so large
+//! documents stay cheap to highlight while typing.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use dashmap::DashMap;
+use tower_lsp::lsp_types::{
+    SemanticToken, SemanticTokenModifier, SemanticTokenType, SemanticTokens, SemanticTokensDelta,
+    SemanticTokensDeltaParams, SemanticTokensEdit, SemanticTokensFullDeltaResult,
+    SemanticTokensLegend, SemanticTokensParams, SemanticTokensRangeParams, Url,
+};
+use typst_analyzer_analysis::highlight::{tokens, Modifier, Token, TokenType};
+
+use crate::backend::Backend;
+use crate::document::Document;
+use crate::position::{offset_to_position, range_to_offsets, PositionEncoding};
+use crate::prelude::*;
+
+/// The tokens sent last for every document, to send only the difference next time.
+#[derive(Debug, Default)]
+pub struct SemanticTokensCache {
+    previous: DashMap<Url, SemanticTokens>,
+    next_id: AtomicU64,
+}
+
+impl SemanticTokensCache {
+    /// Remembers the tokens sent for a document and gives them an id.
+    fn store(&self, uri: Url, data: Vec<SemanticToken>) -> SemanticTokens {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let tokens = SemanticTokens {
+            result_id: Some(id.to_string()),
+            data,
+        };
+        self.previous.insert(uri, tokens.clone());
+        tokens
+    }
+
+    /// Forgets the tokens of a closed document.
+    pub fn remove(&self, uri: &Url) {
+        self.previous.remove(uri);
+    }
+}
+
+/// The token types and modifiers, the indices in the legend are the ones used in the tokens.
+pub(crate) fn legend() -> SemanticTokensLegend {
+    SemanticTokensLegend {
+        token_types: TokenType::LIST
+            .iter()
+            .map(|kind| SemanticTokenType::new(kind.name()))
+            .collect(),
+        token_modifiers: Modifier::LIST
+            .iter()
+            .map(|modifier| SemanticTokenModifier::new(modifier.name()))
+            .collect(),
+    }
+}
+
+pub(crate) trait HandleSemanticTokens {
+    fn provide_semantic_tokens_full(
+        &self,
+        params: SemanticTokensParams,
+    ) -> Result<SemanticTokens, Error>;
+
+    fn provide_semantic_tokens_range(
+        &self,
+        params: SemanticTokensRangeParams,
+    ) -> Result<SemanticTokens, Error>;
+
+    fn provide_semantic_tokens_delta(
+        &self,
+        params: SemanticTokensDeltaParams,
+    ) -> Result<SemanticTokensFullDeltaResult, Error>;
+}
+
+impl HandleSemanticTokens for Backend {
+    fn provide_semantic_tokens_full(
+        &self,
+        params: SemanticTokensParams,
+    ) -> Result<SemanticTokens, Error> {
+        let uri = params.text_document.uri;
+        let data = self
+            .with_document(&uri, |doc| {
+                encode(doc, tokens(doc.source.root()), self.position_encoding())
+            })
+            .ok_or(anyhow!("document is not open"))?;
+        Ok(self.semantic_tokens.store(uri, data))
+    }
+
+    // Tokens of a range are not remembered, they are no base for a difference
+    fn provide_semantic_tokens_range(
+        &self,
+        params: SemanticTokensRangeParams,
+    ) -> Result<SemanticTokens, Error> {
+        let uri = params.text_document.uri;
+        let encoding = self.position_encoding();
+        let data = self
+            .with_document(&uri, |doc| {
+                let range = range_to_offsets(&doc.text, params.range, encoding)?;
+                let tokens = tokens(doc.source.root())
+                    .into_iter()
+                    .filter(|token| token.range.start < range.end && range.start < token.range.end)
+                    .collect();
+                Some(encode(doc, tokens, encoding))
+            })
+            .ok_or(anyhow!("document is not open"))?
+            .ok_or(anyhow!("range is outside of the document"))?;
+        Ok(SemanticTokens {
+            result_id: None,
+            data,
+        })
+    }
+
+    fn provide_semantic_tokens_delta(
+        &self,
+        params: SemanticTokensDeltaParams,
+    ) -> Result<SemanticTokensFullDeltaResult, Error> {
+        let uri = params.text_document.uri;
+        let data = self
+            .with_document(&uri, |doc| {
+                encode(doc, tokens(doc.source.root()), self.position_encoding())
+            })
+            .ok_or(anyhow!("document is not open"))?;
+        let previous = self
+            .semantic_tokens
+            .previous
+            .get(&uri)
+            .filter(|previous| previous.result_id.as_ref() == Some(&params.previous_result_id))
+            .map(|previous| previous.data.clone());
+        let tokens = self.semantic_tokens.store(uri, data);
+        // The client has tokens we no longer know, it gets all of them again
+        let Some(previous) = previous else {
+            return Ok(SemanticTokensFullDeltaResult::Tokens(tokens));
+        };
+        Ok(SemanticTokensFullDeltaResult::TokensDelta(
+            SemanticTokensDelta {
+                result_id: tokens.result_id,
+                edits: edits(&previous, &tokens.data),
+            },
+        ))
+    }
+}
+
+/// Encodes tokens relative to each other, as the protocol wants them.
+///
+/// Tokens spanning several lines are split into one token per line, not every client supports
+/// tokens across lines.
+fn encode(doc: &Document, tokens: Vec<Token>, encoding: PositionEncoding) -> Vec<SemanticToken> {
+    let text = doc.source.text();
+    let mut data = Vec::with_capacity(tokens.len());
+    let (mut line, mut character) = (0, 0);
+    for token in tokens {
+        let mut start = token.range.start;
+        while start < token.range.end {
+            let end = text
+                .get(start..token.range.end)
+                .and_then(|rest| rest.find('\n'))
+                .map_or(token.range.end, |newline| start + newline);
+            let piece_end = match text.get(..end) {
+                Some(before) if before.ends_with('\r') => end - 1,
+                _ => end,
+            };
+            let (Some(from), Some(to)) = (
+                offset_to_position(&doc.text, start, encoding),
+                offset_to_position(&doc.text, piece_end, encoding),
+            ) else {
+                break;
+            };
+            // Other line breaks than `\n` leave the token on a line of its own
+            if from.line == to.line && to.character > from.character {
+                let delta_line = from.line - line;
+                let delta_start = match delta_line {
+                    0 => from.character - character,
+                    _ => from.character,
+                };
+                data.push(SemanticToken {
+                    delta_line,
+                    delta_start,
+                    length: to.character - from.character,
+                    token_type: token.kind as u32,
+                    token_modifiers_bitset: token.modifiers,
+                });
+                (line, character) = (from.line, from.character);
+            }
+            start = end + 1;
+        }
+    }
+    data
+}
+
+/// The edit turning the previous tokens into the current ones, everything between the tokens
+/// they start and end with is replaced.
+fn edits(previous: &[SemanticToken], current: &[SemanticToken]) -> Vec<SemanticTokensEdit> {
+    let prefix = previous
+        .iter()
+        .zip(current)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix = previous[prefix..]
+        .iter()
+        .rev()
+        .zip(current[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let deleted = previous.len() - prefix - suffix;
+    let inserted = &current[prefix..current.len() - suffix];
+    if deleted == 0 && inserted.is_empty() {
+        return Vec::new();
+    }
+    // Positions in the edit count the numbers of the tokens, five per token
+    vec![SemanticTokensEdit {
+        start: (prefix * 5) as u32,
+        delete_count: (deleted * 5) as u32,
+        data: Some(inserted.to_vec()),
+    }]
+}
+
+#[test]
+fn edits_test() {
+    let token = |delta_line: u32, length: u32| SemanticToken {
+        delta_line,
+        delta_start: 0,
+        length,
+        token_type: 0,
+        token_modifiers_bitset: 0,
+    };
+    let previous = [token(0, 1), token(1, 2), token(1, 3), token(1, 4)];
+    assert!(edits(&previous, &previous).is_empty());
+    let current = [
+        token(0, 1),
+        token(1, 5),
+        token(1, 6),
+        token(1, 3),
+        token(1, 4),
+    ];
+    assert_eq!(
+        edits(&previous, &current),
+        [SemanticTokensEdit {
+            start: 5,
+            delete_count: 5,
+            data: Some(vec![token(1, 5), token(1, 6)]),
+        }]
+    );
+    assert_eq!(
+        edits(&previous, &previous[..2]),
+        [SemanticTokensEdit {
+            start: 10,
+            delete_count: 10,
+            data: Some(Vec::new()),
+        }]
+    );
+}
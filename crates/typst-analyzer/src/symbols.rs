@@ -1,212 +1,286 @@
 use crate::prelude::*;
-use tower_lsp::lsp_types::{DidChangeTextDocumentParams, Location, Position, Range, Url};
-use typst_syntax::{Source, SyntaxKind};
+use ropey::Rope;
+use tower_lsp::lsp_types::{Location, Range, Url};
+use typst_analyzer_analysis::definition::{exports, BindingKind};
+use typst_analyzer_analysis::outline::{outline, OutlineItem, OutlineKind};
+use typst_syntax::ast::AstNode;
+use typst_syntax::{ast, FileId, LinkedNode, SyntaxKind};
 
 use crate::backend::Backend;
+use crate::document::Document;
+use crate::position::{offsets_to_range, PositionEncoding};
+use crate::workspace::symbols::{Dependency, DependencyKind, FileSymbols, SymbolKind, SymbolRole};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Symbol {
     pub name: String,            // Symbol's name (e.g., function name)
     pub location: Location,      // Where the symbol is in the document
     pub symbol_type: SyntaxKind, // Type of symbol (e.g., "FunCall", "Ref")
+    pub file: FileId,            // The file containing the symbol
 }
 
 pub(crate) trait SymbolTable {
-    fn populate_symbol_table(
+    fn populate_symbol_table(&self, uri: &Url, document: &Document) -> Result<Vec<Symbol>, Error>;
+
+    fn collect_symbols(
         &self,
-        params: DidChangeTextDocumentParams,
-    ) -> Result<Vec<Symbol>, Error>;
+        uri: &Url,
+        document: &Document,
+    ) -> Result<(Vec<Symbol>, FileSymbols), Error>;
 }
 
 // the node kind is:
 //
 // FuncCall: 28 [Ident: "footnote", Args: 20 [ContentBlock: 20 [LeftBracket: "[", Markup: 18 [Text: "this is a footnote"], RightBracket: "]"]]]
 impl SymbolTable for Backend {
-    // this is a fallible function location is varying from the result we got.
-    // need additional tesing since inaccurate maipping of symbols led messing up users document when we relay on on it
-    fn populate_symbol_table(
+    // Collects the symbols of the document, replaces the entries of the document in the symbol
+    // index with them and returns its labels and references.
+    fn populate_symbol_table(&self, uri: &Url, document: &Document) -> Result<Vec<Symbol>, Error> {
+        let (symbol_vec, file_symbols) = self.collect_symbols(uri, document)?;
+        self.symbol_index.update(uri.clone(), file_symbols);
+        Ok(symbol_vec)
+    }
+
+    // Collects every label and reference of the document, without touching the index. The files
+    // the document includes, imports or cites from are recorded too, as are its headings and top
+    // level bindings.
+    fn collect_symbols(
         &self,
-        params: DidChangeTextDocumentParams,
-    ) -> Result<Vec<Symbol>, Error> {
+        uri: &Url,
+        document: &Document,
+    ) -> Result<(Vec<Symbol>, FileSymbols), Error> {
         let mut symbol_vec = Vec::new();
-        let mut ast_labels = Vec::new();
-        let mut ast_references = Vec::new();
-        let binding = self.ast_map.get(&params.text_document.uri.to_string());
-        if let Some(ast) = &binding {
-            let source = ast.value();
-            for node in ast.root().children() {
-                if node.kind() == SyntaxKind::Label {
-                    // slice out the range of node from ast_map source
-                    if let Some(range) = &ast.value().range(node.span()) {
-                        let ctx = source
-                            .get(range.to_owned())
-                            .ok_or(anyhow!("Failed to get ctx of ast from range"))?;
-                        let loc =
-                            range_to_location(params.text_document.uri.clone(), source, range)?;
-                        let label = ctx
-                            .strip_prefix("<")
-                            .ok_or(anyhow!("failed to strip symbols"))?
-                            .strip_suffix(">")
-                            .ok_or(anyhow!("failed to strip symbols"))?;
-                        let symbol = Symbol {
-                            name: label.to_owned(),
-                            location: loc.clone(),
-                            symbol_type: SyntaxKind::Label,
-                        };
-                        symbol_vec.push(symbol.clone());
-                        ast_labels.push(label);
-                        let symbol_table_re = self
-                            .symbol_table
-                            .insert(symbol.name.clone(), symbol)
-                            .ok_or(anyhow!("failed to map symbols into symbol table\n name: {:?}\nLocation: {:?}", ctx, loc));
-                        match symbol_table_re {
-                            Ok(_symbol_table) => {
-                                //     typ_logger!("symbol_table: {:#?}", symbol_table)
-                            }
-                            Err(_) => continue, // this must do the job
-                        }
-                    }
+        let mut file_symbols = FileSymbols::default();
+        let source = &document.source;
+        let text = &document.text;
+        for node in descendants(source.root()) {
+            // `<label>`, the range covers the angle brackets. In markup it labels the element
+            // before it, in code it is a value like in `ref(<label>)` or `show <label>: ..`
+            if let Some(label) = node.cast::<ast::Label>() {
+                let loc =
+                    range_to_location(uri.clone(), text, &node.range(), self.position_encoding())?;
+                let symbol = Symbol {
+                    name: label.get().to_owned(),
+                    location: loc,
+                    symbol_type: SyntaxKind::Label,
+                    file: source.id(),
+                };
+                symbol_vec.push(symbol.clone());
+                let role = match node.parent_kind() {
+                    Some(SyntaxKind::Markup) => SymbolRole::Definition,
+                    _ => SymbolRole::Reference,
+                };
+                file_symbols.insert(SymbolKind::Label, role, symbol);
+            }
+
+            // `label("name")`, the range covers the quotes. Like `<name>` it labels the element
+            // before it when it is written in markup
+            if let Some((name, range)) = label_call(&node) {
+                let loc = range_to_location(uri.clone(), text, &range, self.position_encoding())?;
+                let symbol = Symbol {
+                    name,
+                    location: loc,
+                    symbol_type: SyntaxKind::Str,
+                    file: source.id(),
+                };
+                symbol_vec.push(symbol.clone());
+                let role = match node.parent_kind() {
+                    Some(SyntaxKind::Markup) => SymbolRole::Definition,
+                    _ => SymbolRole::Reference,
+                };
+                file_symbols.insert(SymbolKind::Label, role, symbol);
+            }
+
+            // `@reference[supplement]`, the range only covers the `@reference` marker
+            if let Some(reference) = node.cast::<ast::Ref>() {
+                let marker = node
+                    .children()
+                    .find(|child| child.kind() == SyntaxKind::RefMarker)
+                    .ok_or(anyhow!("reference without marker"))?;
+                let loc = range_to_location(
+                    uri.clone(),
+                    text,
+                    &marker.range(),
+                    self.position_encoding(),
+                )?;
+                let symbol = Symbol {
+                    name: reference.target().to_owned(),
+                    location: loc,
+                    symbol_type: SyntaxKind::Ref,
+                    file: source.id(),
+                };
+                symbol_vec.push(symbol.clone());
+                file_symbols.insert(SymbolKind::Label, SymbolRole::Reference, symbol);
+            }
+
+            // `#include "chapter.typ"` and `#import "template.typ": ..`
+            let source_path = match node.kind() {
+                SyntaxKind::ModuleInclude => node
+                    .cast::<ast::ModuleInclude>()
+                    .map(|include| (DependencyKind::Include, include.source())),
+                SyntaxKind::ModuleImport => node
+                    .cast::<ast::ModuleImport>()
+                    .map(|import| (DependencyKind::Import, import.source())),
+                _ => None,
+            };
+            if let Some((kind, ast::Expr::Str(path))) = source_path {
+                if let Some(dependency) = self.dependency(uri, text, &node, kind, path) {
+                    file_symbols.add_dependency(dependency);
                 }
+            }
 
-                if node.kind() == SyntaxKind::Ref {
-                    // slice out the range of node from ast_map source
-                    if let Some(range) = &ast.value().range(node.span()) {
-                        let ctx = source
-                            .get(range.to_owned())
-                            .ok_or(anyhow!("Failed to get ctx of ast from range"))?;
-                        let loc =
-                            range_to_location(params.text_document.uri.clone(), source, range)?;
-                        let reference = ctx
-                            .strip_prefix("@")
-                            .ok_or(anyhow!("failed to strip symbols"))?;
-                        let symbol = Symbol {
-                            name: reference.to_owned(),
-                            location: loc.clone(),
-                            symbol_type: SyntaxKind::Ref,
-                        };
-                        symbol_vec.push(symbol.clone());
-                        ast_references.push(reference);
-                        let symbol_table_re = self
-                            .symbol_table
-                            .insert(symbol.name.clone(), symbol)
-                            .ok_or(anyhow!("failed to map symbols into symbol table\n name: {:?}\nLocation: {:?}", ctx, loc));
-                        match symbol_table_re {
-                            Ok(_symbol_table) => {
-                                //     typ_logger!("symbol_table: {:#?}", symbol_table)
-                            }
-                            Err(_) => continue, // this must do the job
+            // `#bibliography("refs.yml")` or `#bibliography(("a.yml", "b.bib"))`
+            if let Some(call) = node.cast::<ast::FuncCall>() {
+                if matches!(call.callee(), ast::Expr::Ident(ident) if ident.as_str() == "bibliography")
+                {
+                    for path in bibliography_paths(call) {
+                        let kind = DependencyKind::Bibliography;
+                        if let Some(dependency) = self.dependency(uri, text, &node, kind, path) {
+                            file_symbols.add_dependency(dependency);
                         }
                     }
                 }
             }
-        }
 
-        let mut diagnostic_item = Vec::new();
-        let missing = find_missing_items(&ast_labels, &ast_references);
-        for s in &symbol_vec {
-            for i in &missing {
-                if s.name == *i {
-                    diagnostic_item.push(s.clone());
+            // `#set document(title: ..)`
+            if let Some(rule) = node.cast::<ast::SetRule>() {
+                if matches!(rule.target(), ast::Expr::Ident(ident) if ident.as_str() == "document")
+                {
+                    file_symbols.sets_document = true;
                 }
             }
         }
 
-        // typ_logger!("symbol table: {:#?}", symbol_vec);
-        // typ_logger!("labels: {:#?}", ast_labels);
-        // typ_logger!("references: {:#?}", ast_references);
-        // typ_logger!("missing: {:#?}", missing);
-        Ok(diagnostic_item)
+        // Headings, for searching the whole workspace
+        let outline = outline(source.root());
+        let mut headings = Vec::new();
+        collect_headings(&outline, &mut headings);
+        for heading in headings {
+            let loc = range_to_location(
+                uri.clone(),
+                text,
+                &heading.selection,
+                self.position_encoding(),
+            )?;
+            let symbol = Symbol {
+                name: heading.name.clone(),
+                location: loc,
+                symbol_type: SyntaxKind::Heading,
+                file: source.id(),
+            };
+            file_symbols.insert(SymbolKind::Heading, SymbolRole::Definition, symbol);
+        }
+
+        // `#let conf(..) = ..`, imported names are left to the file that binds them
+        for binding in exports(source.root()) {
+            let kind = match binding.kind {
+                BindingKind::Function => SymbolKind::Function,
+                BindingKind::Variable => SymbolKind::Variable,
+                _ => continue,
+            };
+            let loc = range_to_location(
+                uri.clone(),
+                text,
+                &binding.ident.range(),
+                self.position_encoding(),
+            )?;
+            let symbol = Symbol {
+                name: binding.name().to_owned(),
+                location: loc,
+                symbol_type: SyntaxKind::LetBinding,
+                file: source.id(),
+            };
+            file_symbols.insert(kind, SymbolRole::Definition, symbol);
+        }
+
+        Ok((symbol_vec, file_symbols))
+    }
+}
+
+impl Backend {
+    /// A dependency on the file at `path`, written in the string literal below `node`.
+    fn dependency(
+        &self,
+        uri: &Url,
+        text: &Rope,
+        node: &LinkedNode,
+        kind: DependencyKind,
+        path: ast::Str,
+    ) -> Option<Dependency> {
+        let target = self.resolve_source_path(uri, &path.get())?;
+        let range = node.find(path.span())?.range();
+        let location =
+            range_to_location(uri.clone(), text, &range, self.position_encoding()).ok()?;
+        Some(Dependency {
+            kind,
+            target,
+            location,
+        })
     }
 }
 
-impl Backend {}
+/// The headings of an outline and of the sections below them.
+fn collect_headings<'a>(items: &'a [OutlineItem], headings: &mut Vec<&'a OutlineItem>) {
+    for item in items {
+        if matches!(item.kind, OutlineKind::Heading(_)) {
+            headings.push(item);
+            collect_headings(&item.children, headings);
+        }
+    }
+}
 
-pub(crate) fn find_missing_items<T: Eq + std::hash::Hash + Clone>(
-    vec1: &[T],
-    vec2: &[T],
-) -> Vec<T> {
-    // Convert vec2 to a HashSet for quick lookups
-    let set2: std::collections::HashSet<_> = vec2.iter().cloned().collect();
+/// The name given to a `label("name")` call and the range of the string.
+fn label_call(node: &LinkedNode) -> Option<(String, core::ops::Range<usize>)> {
+    let call = node.cast::<ast::FuncCall>()?;
+    if !matches!(call.callee(), ast::Expr::Ident(ident) if ident.as_str() == "label") {
+        return None;
+    }
+    let name = call.args().items().find_map(|arg| match arg {
+        ast::Arg::Pos(ast::Expr::Str(name)) => Some(name),
+        _ => None,
+    })?;
+    let range = node.find(name.span())?.range();
+    Some((name.get().as_str().to_owned(), range))
+}
 
-    // Find elements in vec1 that are not in vec2
-    vec1.iter()
-        .filter(|item| !set2.contains(item))
-        .cloned()
-        .collect()
+/// The path arguments of a `bibliography` call, a single path or an array of paths.
+fn bibliography_paths(call: ast::FuncCall) -> Vec<ast::Str> {
+    let first = call.args().items().find_map(|arg| match arg {
+        ast::Arg::Pos(expr) => Some(expr),
+        _ => None,
+    });
+    match first {
+        Some(ast::Expr::Str(path)) => vec![path],
+        Some(ast::Expr::Array(paths)) => paths
+            .items()
+            .filter_map(|item| match item {
+                ast::ArrayItem::Pos(ast::Expr::Str(path)) => Some(path),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
 }
 
+/// Converts a byte range of the document into an LSP location.
 pub(crate) fn range_to_location(
     uri: Url,
-    source: &Source,
+    rope: &Rope,
     range: &core::ops::Range<usize>,
+    encoding: PositionEncoding,
 ) -> Result<Location, Error> {
-    let (starting_line, starting_char) = (
-        source
-            .byte_to_line(range.start)
-            .ok_or(anyhow!("Failed to get Location from Source"))?,
-        source
-            .byte_to_column(range.start)
-            .ok_or(anyhow!("Failed to get Location from Source"))?,
-    );
-    let (ending_line, ending_char) = (
-        source
-            .byte_to_line(range.end)
-            .ok_or(anyhow!("Failed to get Location from Source"))?,
-        source
-            .byte_to_column(range.end)
-            .ok_or(anyhow!("Failed to get Location from Source"))?,
-    );
     Ok(Location {
         uri,
-        range: Range {
-            start: Position {
-                line: starting_line as u32,
-                character: starting_char as u32,
-            },
-            end: Position {
-                line: ending_line as u32,
-                character: ending_char as u32,
-            },
-        },
+        range: range_to_lsp_range(rope, range, encoding)?,
     })
 }
 
-//pub(crate) fn range_to_position(
-//    range: &core::ops::Range<usize>,
-//) -> Result<Position, anyhow::Error> {
-//    Ok(Position {
-//        line: todo!(),
-//        character: todo!(),
-//    })
-//}
+/// Converts a byte range of the document into an LSP range, counting characters in code units of
+/// the negotiated encoding.
 pub(crate) fn range_to_lsp_range(
-    source: &Source,
+    rope: &Rope,
     range: &core::ops::Range<usize>,
+    encoding: PositionEncoding,
 ) -> Result<Range, Error> {
-    let (starting_line, starting_char) = (
-        source
-            .byte_to_line(range.start)
-            .ok_or(anyhow!("Failed to get Range from Source"))?,
-        source
-            .byte_to_column(range.start)
-            .ok_or(anyhow!("Failed to get Range from Source"))?,
-    );
-    let (ending_line, ending_char) = (
-        source
-            .byte_to_line(range.end)
-            .ok_or(anyhow!("Failed to get Range from Source"))?,
-        source
-            .byte_to_column(range.end)
-            .ok_or(anyhow!("Failed to get Range from Source"))?,
-    );
-    Ok(Range {
-        start: Position {
-            line: starting_line as u32,
-            character: starting_char as u32,
-        },
-        end: Position {
-            line: ending_line as u32,
-            character: ending_char as u32,
-        },
-    })
+    offsets_to_range(rope, range, encoding).ok_or(anyhow!("Failed to get Range from document"))
 }
@@ -1 +1,605 @@
+//! Indexing of the Typst files in the workspace folders.
+//!
+//! Only open documents are sent to us by the client, but labels referenced from one file are
+//! usually defined in another one. Every `.typ` file below a workspace folder is therefore parsed
+//! in the background and fed into the symbol index, which keeps the parsed files for requests on
+//! files that are not open. Files outside the workspace that a document includes or imports are
+//! indexed when the document is analysed. Open documents always win over the content on disk.
+//!
+//! Afterwards the client watches the workspace for us, so files changed outside the editor, by a
+//! `git checkout` or a script, are picked up without restarting the server.
 
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+
+use anyhow::Error;
+use dashmap::Entry;
+use tower_lsp::lsp_types::notification::{DidChangeWatchedFiles, Notification, Progress};
+use tower_lsp::lsp_types::request::WorkDoneProgressCreate;
+use tower_lsp::lsp_types::{
+    DidChangeWatchedFilesRegistrationOptions, FileChangeType, FileEvent, FileSystemWatcher,
+    GlobPattern, NumberOrString, ProgressParams, ProgressParamsValue, Registration, Url,
+    WorkDoneProgress, WorkDoneProgressBegin, WorkDoneProgressCreateParams, WorkDoneProgressEnd,
+    WorkDoneProgressReport,
+};
+use walkdir::{DirEntry, WalkDir};
+
+use crate::backend::Backend;
+use crate::config::project::CONFIG_FILE;
+use crate::document::Document;
+use crate::error_ctx::TypError;
+use crate::symbols::SymbolTable;
+use crate::typ_logger;
+use crate::workspace::symbols::{DependencyKind, FileSymbols};
+
+/// Files the client is asked to watch: Typst sources, bibliographies, images, fonts and config
+/// files.
+const WATCHED_FILES: [&str; 5] = [
+    "**/*.typ",
+    "**/*.{yml,yaml,bib}",
+    "**/*.{png,jpg,jpeg,gif,svg}",
+    "**/*.{ttf,otf,ttc,otc,woff,woff2}",
+    "**/typst-analyzer.toml",
+];
+
+/// What a watched file is to the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WatchedFile {
+    /// A Typst source, indexed unless it is open.
+    Typst,
+    /// A bibliography, its keys are cited in the documents.
+    Bibliography,
+    /// An image, offered by path completions.
+    Image,
+    /// A font, offered by font completions.
+    Font,
+    /// A project or global config file.
+    Config,
+}
+
+impl WatchedFile {
+    fn of(path: &Path) -> Option<Self> {
+        if path.file_name().is_some_and(|name| name == CONFIG_FILE) {
+            return Some(WatchedFile::Config);
+        }
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "typ" => Some(WatchedFile::Typst),
+            "yml" | "yaml" | "bib" => Some(WatchedFile::Bibliography),
+            "png" | "jpg" | "jpeg" | "gif" | "svg" => Some(WatchedFile::Image),
+            "ttf" | "otf" | "ttc" | "otc" | "woff" | "woff2" => Some(WatchedFile::Font),
+            _ => None,
+        }
+    }
+}
+
+/// Number of files read and parsed in one go while indexing the workspace.
+const INDEX_BATCH: usize = 25;
+
+/// Helper function to filter out hidden files and directories, like `.git`
+fn is_hidden(entry: &DirEntry) -> bool {
+    entry.depth() > 0
+        && entry
+            .file_name()
+            .to_str()
+            .map(|s| s.starts_with("."))
+            .unwrap_or(false)
+}
+
+/// Returns every `.typ` file below `root`.
+pub(crate) fn typst_files(root: &Path) -> Vec<PathBuf> {
+    WalkDir::new(root)
+        .into_iter()
+        .filter_entry(|entry| !is_hidden(entry))
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .map(|entry| entry.into_path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "typ"))
+        .collect()
+}
+
+/// Reports the progress of a long running task to the client with `$/progress`.
+struct WorkDone<'a> {
+    backend: &'a Backend,
+    token: Option<NumberOrString>,
+}
+
+impl<'a> WorkDone<'a> {
+    /// Creates a progress token and begins reporting, if the client supports it.
+    async fn begin(backend: &'a Backend, title: &str) -> Self {
+        static NEXT_TOKEN: AtomicU32 = AtomicU32::new(0);
+        let supported = backend
+            .client_capabilities()
+            .window
+            .and_then(|window| window.work_done_progress)
+            .unwrap_or(false);
+        let token = NumberOrString::String(format!(
+            "typst-analyzer/{}",
+            NEXT_TOKEN.fetch_add(1, Ordering::Relaxed)
+        ));
+        let created = supported
+            && backend
+                .client
+                .send_request::<WorkDoneProgressCreate>(WorkDoneProgressCreateParams {
+                    token: token.clone(),
+                })
+                .await
+                .is_ok();
+        let work_done = Self {
+            backend,
+            token: created.then_some(token),
+        };
+        work_done
+            .send(WorkDoneProgress::Begin(WorkDoneProgressBegin {
+                title: title.to_owned(),
+                cancellable: Some(false),
+                message: None,
+                percentage: Some(0),
+            }))
+            .await;
+        work_done
+    }
+
+    async fn report(&self, message: String, percentage: u32) {
+        self.send(WorkDoneProgress::Report(WorkDoneProgressReport {
+            cancellable: Some(false),
+            message: Some(message),
+            percentage: Some(percentage),
+        }))
+        .await;
+    }
+
+    async fn end(self, message: String) {
+        self.send(WorkDoneProgress::End(WorkDoneProgressEnd {
+            message: Some(message),
+        }))
+        .await;
+    }
+
+    async fn send(&self, progress: WorkDoneProgress) {
+        if let Some(token) = &self.token {
+            self.backend
+                .client
+                .send_notification::<Progress>(ProgressParams {
+                    token: token.clone(),
+                    value: ProgressParamsValue::WorkDone(progress),
+                })
+                .await;
+        }
+    }
+}
+
+impl Backend {
+    /// Parses every Typst file in the given workspace folders and feeds the symbol index.
+    ///
+    /// Walking the folders and parsing the files blocks, it runs on the blocking thread pool in
+    /// batches so requests are answered while a large workspace is indexed.
+    pub(crate) async fn index_workspace_folders(&self, folders: Vec<Url>) {
+        let roots: Vec<PathBuf> = folders
+            .iter()
+            .filter_map(|folder| folder.to_file_path().ok())
+            .collect();
+        let files = tokio::task::spawn_blocking(move || {
+            roots
+                .iter()
+                .flat_map(|root| typst_files(root))
+                .collect::<Vec<_>>()
+        })
+        .await
+        .unwrap_or_default();
+        let progress = WorkDone::begin(self, "Indexing Typst files").await;
+        let total = files.len();
+        let mut done = 0;
+        for batch in files.chunks(INDEX_BATCH) {
+            let backend = self.clone();
+            let batch = batch.to_vec();
+            let analysed = tokio::task::spawn_blocking(move || {
+                batch
+                    .into_iter()
+                    .filter_map(|path| Url::from_file_path(path).ok())
+                    .map(|uri| {
+                        let symbols = backend.analyse_file_on_disk(&uri);
+                        (uri, symbols)
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .await
+            .unwrap_or_default();
+            for (uri, symbols) in analysed {
+                self.store_file_on_disk(uri, symbols);
+            }
+            // Reporting every file would flood the client on large workspaces
+            done += INDEX_BATCH.min(total - done);
+            let percentage = (done * 100 / total.max(1)) as u32;
+            progress
+                .report(format!("{done}/{total} files"), percentage)
+                .await;
+        }
+        progress.end(format!("Indexed {total} files")).await;
+        // Labels of open documents may be defined in files that were not indexed before
+        let open: Vec<Url> = self.documents.iter().map(|doc| doc.key().clone()).collect();
+        for uri in open {
+            self.schedule_diagnostics(uri, Duration::ZERO);
+        }
+    }
+
+    /// Reads a file from disk and updates its entries in the symbol index.
+    ///
+    /// Open documents are skipped, their content in the editor is newer than the one on disk.
+    pub(crate) async fn index_file_from_disk(&self, uri: &Url) {
+        if self.documents.contains_key(uri) {
+            return;
+        }
+        let backend = self.clone();
+        let task_uri = uri.clone();
+        let Ok(symbols) =
+            tokio::task::spawn_blocking(move || backend.analyse_file_on_disk(&task_uri)).await
+        else {
+            return;
+        };
+        self.store_file_on_disk(uri.clone(), symbols);
+    }
+
+    /// Indexes the files a document includes or imports that are not indexed yet, like files
+    /// outside the workspace, and in turn the files they refer to.
+    pub(crate) async fn index_dependencies(&self, uri: &Url) {
+        let mut queue = vec![uri.clone()];
+        while let Some(file) = queue.pop() {
+            for kind in [DependencyKind::Include, DependencyKind::Import] {
+                for target in self.symbol_index.dependencies(&file, kind) {
+                    if self.symbol_index.with_file(&target, |_| ()).is_some() {
+                        continue;
+                    }
+                    self.index_file_from_disk(&target).await;
+                    queue.push(target);
+                }
+            }
+        }
+    }
+
+    /// Reads and analyses a file on disk, without touching the index. Blocks.
+    fn analyse_file_on_disk(&self, uri: &Url) -> Option<FileSymbols> {
+        let path = uri.to_file_path().ok()?;
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(err) => {
+                typ_logger!("failed to read {}: {}", path.display(), err);
+                return None;
+            }
+        };
+        let document = Document::new(uri, text, 0);
+        match self.collect_symbols(uri, &document) {
+            Ok((_, mut symbols)) => {
+                symbols.document = Some(document);
+                Some(symbols)
+            }
+            Err(err) => {
+                typ_logger!("{}", TypError::NonCriticalError(err.to_string().as_str()));
+                None
+            }
+        }
+    }
+
+    /// Stores the symbols of a file read from disk, or forgets the file if it could not be read.
+    ///
+    /// The file may have been opened while it was read. Its entry in the open documents stays
+    /// locked until the index is updated, so a `didOpen` either came first and the disk content is
+    /// dropped, or it waits and reindexes the file with the content of the editor afterwards.
+    fn store_file_on_disk(&self, uri: Url, symbols: Option<FileSymbols>) {
+        let entry = self.documents.entry(uri.clone());
+        if let Entry::Vacant(_) = entry {
+            match symbols {
+                Some(symbols) => self.symbol_index.update(uri, symbols),
+                None => self.symbol_index.remove(&uri),
+            }
+        }
+        drop(entry);
+    }
+
+    /// Asks the client to watch the files the analysis depends on.
+    ///
+    /// Only possible if the client supports registering the watchers dynamically.
+    pub(crate) async fn register_file_watchers(&self) -> Result<(), Error> {
+        let supported = self
+            .client_capabilities()
+            .workspace
+            .and_then(|workspace| workspace.did_change_watched_files)
+            .and_then(|watched| watched.dynamic_registration)
+            .unwrap_or(false);
+        if !supported {
+            return Ok(());
+        }
+        let options = DidChangeWatchedFilesRegistrationOptions {
+            watchers: WATCHED_FILES
+                .iter()
+                .map(|glob| FileSystemWatcher {
+                    glob_pattern: GlobPattern::String((*glob).to_owned()),
+                    kind: None,
+                })
+                .collect(),
+        };
+        self.client
+            .register_capability(vec![Registration {
+                id: "typst-analyzer/watched-files".to_owned(),
+                method: DidChangeWatchedFiles::METHOD.to_owned(),
+                register_options: Some(serde_json::to_value(options)?),
+            }])
+            .await?;
+        Ok(())
+    }
+
+    /// Updates the index after files were created, changed or deleted on disk.
+    ///
+    /// Open documents are left alone, the editor owns their content. What was found in changed
+    /// bibliographies, images and fonts is forgotten. Since any of these files may hold a label or
+    /// a bibliography entry used elsewhere, the diagnostics of every open document are refreshed
+    /// afterwards. A changed config file reloads the settings instead.
+    pub(crate) async fn handle_watched_files(&self, changes: Vec<FileEvent>) {
+        let mut config_changed = false;
+        let mut files_changed = false;
+        for change in changes {
+            let Ok(path) = change.uri.to_file_path() else {
+                continue;
+            };
+            match WatchedFile::of(&path) {
+                Some(WatchedFile::Config) => {
+                    self.load_config_file(&path).await;
+                    config_changed = true;
+                }
+                Some(WatchedFile::Typst) if !self.documents.contains_key(&change.uri) => {
+                    if change.typ == FileChangeType::DELETED {
+                        self.symbol_index.remove(&change.uri);
+                    } else {
+                        self.index_file_from_disk(&change.uri).await;
+                    }
+                    files_changed = true;
+                }
+                Some(WatchedFile::Bibliography) => {
+                    self.resources.clear_bibliography(&path);
+                    files_changed = true;
+                }
+                Some(WatchedFile::Image) => {
+                    self.resources.clear_images();
+                    files_changed = true;
+                }
+                Some(WatchedFile::Font) => {
+                    self.resources.clear_fonts();
+                    files_changed = true;
+                }
+                Some(WatchedFile::Typst) | None => {}
+            }
+        }
+        if config_changed {
+            self.apply_settings().await;
+        } else if files_changed {
+            // A checkout changes many files at once, debounce like a regular edit
+            let open: Vec<Url> = self.documents.iter().map(|doc| doc.key().clone()).collect();
+            for uri in open {
+                self.schedule_diagnostics(uri, self.diagnostics.delay());
+            }
+        }
+    }
+
+    /// Whether a file lies below one of the workspace folders.
+    pub(crate) fn is_in_workspace(&self, uri: &Url) -> bool {
+        let Ok(path) = uri.to_file_path() else {
+            return false;
+        };
+        self.workspace_folders().iter().any(|folder| {
+            folder
+                .to_file_path()
+                .is_ok_and(|folder| path.starts_with(folder))
+        })
+    }
+}
+
+/// Creates a fresh directory below the temp directory with the given files, for tests.
+#[cfg(test)]
+pub(crate) fn temp_workspace(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let root = std::env::temp_dir().join(format!("typst-analyzer-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    for (path, text) in files {
+        let path = root.join(path);
+        if let Some(dir) = path.parent() {
+            assert!(std::fs::create_dir_all(dir).is_ok());
+        }
+        assert!(std::fs::write(path, text).is_ok());
+    }
+    root
+}
+
+#[test]
+fn typst_files_test() {
+    let root = temp_workspace(
+        "typst-files",
+        &[
+            ("main.typ", ""),
+            ("chapters/one.typ", ""),
+            (".git/hidden.typ", ""),
+            ("notes.txt", ""),
+        ],
+    );
+    let mut files = typst_files(&root);
+    files.sort();
+    assert_eq!(
+        files,
+        [root.join("chapters/one.typ"), root.join("main.typ")]
+    );
+    let _ = std::fs::remove_dir_all(root);
+}
+
+#[tokio::test]
+async fn index_workspace_test() {
+    use crate::workspace::symbols::{SymbolKind, SymbolRole};
+
+    let root = temp_workspace(
+        "index",
+        &[
+            ("main.typ", "= Intro <intro>\n#include \"chapter.typ\""),
+            ("chapter.typ", "See @intro."),
+        ],
+    );
+    let backend = Backend::detached();
+    let (folder, main, chapter) = (
+        Url::from_directory_path(&root),
+        Url::from_file_path(root.join("main.typ")),
+        Url::from_file_path(root.join("chapter.typ")),
+    );
+    assert!(folder.is_ok() && main.is_ok() && chapter.is_ok());
+    let (Ok(folder), Ok(main), Ok(chapter)) = (folder, main, chapter) else {
+        return;
+    };
+    let labels = |uri: &Url| {
+        backend.symbol_index.with_file(uri, |file| {
+            file.all(SymbolKind::Label, SymbolRole::Definition)
+                .into_iter()
+                .map(|symbol| symbol.name.clone())
+                .collect::<Vec<_>>()
+        })
+    };
+
+    backend.add_workspace_folder(folder.clone());
+    backend.index_workspace_folders(vec![folder]).await;
+    assert_eq!(labels(&main), Some(vec!["intro".to_owned()]));
+    assert_eq!(
+        backend
+            .symbol_index
+            .dependencies(&main, crate::workspace::symbols::DependencyKind::Include),
+        std::slice::from_ref(&chapter)
+    );
+
+    // An open document is newer than the file on disk, reading the file again keeps it
+    let doc = Document::new(&main, "= Outro <outro>".to_owned(), 1);
+    assert!(backend.populate_symbol_table(&main, &doc).is_ok());
+    backend.documents.insert(main.clone(), doc);
+    backend.index_file_from_disk(&main).await;
+    assert_eq!(labels(&main), Some(vec!["outro".to_owned()]));
+    let on_disk = backend.analyse_file_on_disk(&main);
+    assert!(on_disk.is_some());
+    backend.store_file_on_disk(main.clone(), on_disk);
+    assert_eq!(labels(&main), Some(vec!["outro".to_owned()]));
+
+    // A file deleted on disk leaves the index
+    assert!(std::fs::remove_file(root.join("chapter.typ")).is_ok());
+    backend.index_file_from_disk(&chapter).await;
+    assert!(backend.symbol_index.with_file(&chapter, |_| ()).is_none());
+    let _ = std::fs::remove_dir_all(root);
+}
+
+#[tokio::test]
+async fn watched_files_test() {
+    use tower_lsp::lsp_types::{FileChangeType, FileEvent};
+
+    use crate::workspace::symbols::{SymbolKind, SymbolRole};
+
+    assert_eq!(
+        WatchedFile::of(Path::new("/p/typst-analyzer.toml")),
+        Some(WatchedFile::Config)
+    );
+    assert_eq!(
+        WatchedFile::of(Path::new("/p/main.typ")),
+        Some(WatchedFile::Typst)
+    );
+    assert_eq!(
+        WatchedFile::of(Path::new("/p/refs.bib")),
+        Some(WatchedFile::Bibliography)
+    );
+    assert_eq!(
+        WatchedFile::of(Path::new("/p/figures/plot.PNG")),
+        Some(WatchedFile::Image)
+    );
+    assert_eq!(
+        WatchedFile::of(Path::new("/p/fonts/Inter.otf")),
+        Some(WatchedFile::Font)
+    );
+    assert_eq!(WatchedFile::of(Path::new("/p/notes.md")), None);
+
+    let root = temp_workspace(
+        "watched",
+        &[
+            ("chapter.typ", "= One <one>"),
+            ("open.typ", "= Open <open>"),
+        ],
+    );
+    let backend = Backend::detached();
+    let (chapter, open, config) = (
+        Url::from_file_path(root.join("chapter.typ")),
+        Url::from_file_path(root.join("open.typ")),
+        Url::from_file_path(root.join(CONFIG_FILE)),
+    );
+    assert!(chapter.is_ok() && open.is_ok() && config.is_ok());
+    let (Ok(chapter), Ok(open), Ok(config)) = (chapter, open, config) else {
+        return;
+    };
+    let labels = |uri: &Url| {
+        backend.symbol_index.with_file(uri, |file| {
+            file.all(SymbolKind::Label, SymbolRole::Definition)
+                .into_iter()
+                .map(|symbol| symbol.name.clone())
+                .collect::<Vec<_>>()
+        })
+    };
+    let event = |uri: &Url, typ: FileChangeType| FileEvent {
+        uri: uri.clone(),
+        typ,
+    };
+    backend
+        .documents
+        .insert(open.clone(), Document::new(&open, String::new(), 1));
+
+    // Created and changed files are read again, open documents are left alone
+    backend
+        .handle_watched_files(vec![
+            event(&chapter, FileChangeType::CREATED),
+            event(&open, FileChangeType::CHANGED),
+        ])
+        .await;
+    assert_eq!(labels(&chapter), Some(vec!["one".to_owned()]));
+    assert_eq!(labels(&open), None);
+    assert!(std::fs::write(root.join("chapter.typ"), "= Two <two>").is_ok());
+    backend
+        .handle_watched_files(vec![event(&chapter, FileChangeType::CHANGED)])
+        .await;
+    assert_eq!(labels(&chapter), Some(vec!["two".to_owned()]));
+    backend
+        .handle_watched_files(vec![event(&chapter, FileChangeType::DELETED)])
+        .await;
+    assert_eq!(labels(&chapter), None);
+
+    // A config file is read as soon as it appears
+    assert!(std::fs::write(root.join(CONFIG_FILE), "entry = \"main.typ\"").is_ok());
+    backend
+        .handle_watched_files(vec![event(&config, FileChangeType::CREATED)])
+        .await;
+    assert!(backend.config_files.contains_key(&root.join(CONFIG_FILE)));
+
+    // What was read from a bibliography or the images is forgotten once they change
+    assert!(std::fs::write(root.join("refs.yml"), "first:\n  type: Book\n").is_ok());
+    let bib = Url::from_file_path(root.join("refs.yml"));
+    assert!(bib.is_ok());
+    let Ok(bib) = bib else { return };
+    assert_eq!(
+        *backend.bib_keys(&root.join("refs.yml")),
+        vec!["first".to_owned()]
+    );
+    assert!(backend.project_images(&root).is_empty());
+    assert!(std::fs::write(root.join("refs.yml"), "second:\n  type: Book\n").is_ok());
+    assert!(std::fs::write(root.join("plot.png"), "").is_ok());
+    backend
+        .handle_watched_files(vec![event(&bib, FileChangeType::CHANGED)])
+        .await;
+    assert_eq!(
+        *backend.bib_keys(&root.join("refs.yml")),
+        vec!["second".to_owned()]
+    );
+    assert!(backend.project_images(&root).is_empty());
+    let image = Url::from_file_path(root.join("plot.png"));
+    assert!(image.is_ok());
+    let Ok(image) = image else { return };
+    backend
+        .handle_watched_files(vec![event(&image, FileChangeType::CREATED)])
+        .await;
+    assert_eq!(backend.project_images(&root).len(), 1);
+    let _ = std::fs::remove_dir_all(root);
+}
@@ -1,2 +1,5 @@
 pub(crate) mod fs;
-pub(crate) mod symbols;
+pub(crate) mod modules;
+pub mod project;
+pub(crate) mod resources;
+pub mod symbols;
@@ -0,0 +1,236 @@
+//! Names imported from other files.
+//!
+//! `#import "template.typ": conf` binds `conf` to the top level binding of the same name in
+//! `template.typ`, and `#import "utils.typ" as u` binds the whole module, so `u.helper` is looked
+//! up in `utils.typ`. An imported file may itself import the name from another file, the chain is
+//! followed until the `let` binding that makes it. Files that are not open are looked up as they
+//! were read from disk when they were indexed.
+
+use std::collections::HashSet;
+
+use tower_lsp::lsp_types::{Location, Position, Range, Url};
+use typst_analyzer_analysis::definition::{
+    docs, exports, field_target, ident_at, import_source, imported_path, signature,
+    visible_wildcard_imports, wildcard_imports, Binding, BindingKind, Scopes,
+};
+use typst_syntax::{LinkedNode, SyntaxKind};
+
+use crate::backend::Backend;
+use crate::document::Document;
+use crate::position::{offsets_to_range, position_to_offset};
+
+/// Where a name is bound, after following imports.
+#[derive(Debug, Clone)]
+pub(crate) struct Definition {
+    pub(crate) name: String,
+    pub(crate) kind: BindingKind,
+    /// The identifier making the binding, or the start of the file for a module.
+    pub(crate) location: Location,
+    /// The file of a module binding.
+    pub(crate) module: Option<Url>,
+    pub(crate) signature: String,
+    pub(crate) docs: Option<String>,
+}
+
+/// Files and names already looked up, to stop at import cycles.
+type Visited = HashSet<(Url, String)>;
+
+impl Backend {
+    /// Runs `f` on an open document, or on the file as it was read from disk when it was indexed.
+    ///
+    /// An open document stays locked while `f` runs, `f` may look at other documents but must not
+    /// change any. A file that is neither open nor indexed is not read, requests must not block.
+    pub(crate) fn with_document<R>(&self, uri: &Url, f: impl FnOnce(&Document) -> R) -> Option<R> {
+        if let Some(doc) = self.documents.get(uri) {
+            return Some(f(&doc));
+        }
+        // No lock is held while `f` runs, the copy shares its text and tree with the index
+        let doc = self.symbol_index.disk_document(uri)?;
+        Some(f(&doc))
+    }
+
+    /// Where the identifier at a position is bound, in this file or the one it is imported from.
+    pub(crate) fn definition_at(&self, uri: &Url, position: Position) -> Option<Definition> {
+        self.with_document(uri, |doc| {
+            let offset = position_to_offset(&doc.text, position, self.position_encoding())?;
+            let ident = ident_at(doc.source.root(), offset)?;
+            self.definition_of(uri, doc, &ident)
+        })
+        .flatten()
+    }
+
+    /// Where an identifier of a document is bound.
+    pub(crate) fn definition_of(
+        &self,
+        uri: &Url,
+        doc: &Document,
+        ident: &LinkedNode,
+    ) -> Option<Definition> {
+        self.definition_in(uri, doc, ident, &mut Scopes::default())
+    }
+
+    /// Where an identifier of a document is bound, with the bindings of the blocks of the
+    /// document collected in `scopes`. Resolving many identifiers of a document with the same
+    /// `scopes` collects the bindings of each block only once.
+    pub(crate) fn definition_in<'a>(
+        &self,
+        uri: &Url,
+        doc: &Document,
+        ident: &LinkedNode<'a>,
+        scopes: &mut Scopes<'a>,
+    ) -> Option<Definition> {
+        // `module.item`
+        if let Some(target) = field_target(ident) {
+            if target.kind() != SyntaxKind::Ident {
+                return None;
+            }
+            let module = self.resolve_ident(uri, doc, &target, scopes)?.module?;
+            return self.export(&module, ident.text(), &mut Visited::new());
+        }
+        self.resolve_ident(uri, doc, ident, scopes)
+    }
+
+    fn resolve_ident<'a>(
+        &self,
+        uri: &Url,
+        doc: &Document,
+        ident: &LinkedNode<'a>,
+        scopes: &mut Scopes<'a>,
+    ) -> Option<Definition> {
+        let mut visited = Visited::new();
+        if let Some(binding) = scopes.resolve(ident) {
+            return self.follow(uri, doc, &binding, &mut visited);
+        }
+        visible_wildcard_imports(ident).iter().find_map(|import| {
+            let module = self.resolve_source_path(uri, &import_source(import)?)?;
+            self.export(&module, ident.text(), &mut visited)
+        })
+    }
+
+    /// Follows a binding to the file that makes it.
+    fn follow(
+        &self,
+        uri: &Url,
+        doc: &Document,
+        binding: &Binding,
+        visited: &mut Visited,
+    ) -> Option<Definition> {
+        let module = match binding.kind {
+            // Items of packages and missing files stay at the import
+            BindingKind::Import => match self.imported(uri, binding, visited) {
+                Some(definition) => return Some(definition),
+                None => None,
+            },
+            BindingKind::ModuleAlias | BindingKind::Module => import_source(&binding.binder)
+                .and_then(|source| self.resolve_source_path(uri, &source))
+                // Known files only, a request does not touch the disk
+                .filter(|module| {
+                    self.documents.contains_key(module)
+                        || self.symbol_index.with_file(module, |_| ()).is_some()
+                }),
+            _ => None,
+        };
+        let location = match &module {
+            Some(module) => Location::new(module.clone(), Range::default()),
+            None => Location::new(
+                uri.clone(),
+                offsets_to_range(&doc.text, &binding.ident.range(), self.position_encoding())?,
+            ),
+        };
+        Some(Definition {
+            name: binding.name().to_owned(),
+            kind: binding.kind,
+            location,
+            module,
+            signature: signature(binding),
+            docs: docs(binding),
+        })
+    }
+
+    /// Follows an imported item to its module.
+    fn imported(&self, uri: &Url, binding: &Binding, visited: &mut Visited) -> Option<Definition> {
+        let mut module = self.resolve_source_path(uri, &import_source(&binding.binder)?)?;
+        let mut path = imported_path(binding);
+        let name = path.pop()?;
+        // `import "x.typ": a.b` imports `b` from the module `a` of `x.typ`
+        for segment in path {
+            module = self.export(&module, &segment, visited)?.module?;
+        }
+        self.export(&module, &name, visited)
+    }
+
+    /// Finds a name among the exports of a module.
+    fn export(&self, module: &Url, name: &str, visited: &mut Visited) -> Option<Definition> {
+        if !visited.insert((module.clone(), name.to_owned())) {
+            return None;
+        }
+        self.with_document(module, |doc| {
+            let root = doc.source.root();
+            if let Some(binding) = exports(root)
+                .into_iter()
+                .rev()
+                .find(|binding| binding.name() == name)
+            {
+                return self.follow(module, doc, &binding, visited);
+            }
+            wildcard_imports(root).iter().find_map(|import| {
+                let imported = self.resolve_source_path(module, &import_source(import)?)?;
+                self.export(&imported, name, visited)
+            })
+        })
+        .flatten()
+    }
+}
+
+#[tokio::test]
+async fn with_document_test() {
+    use crate::symbols::SymbolTable;
+    use crate::workspace::fs::temp_workspace;
+
+    let root = temp_workspace(
+        "with-document",
+        &[
+            ("workspace/main.typ", "= On disk"),
+            ("outside/lib.typ", "#let helper = 1"),
+        ],
+    );
+    let folder = Url::from_directory_path(root.join("workspace"));
+    let (main, lib) = (
+        Url::from_file_path(root.join("workspace/main.typ")),
+        Url::from_file_path(root.join("outside/lib.typ")),
+    );
+    assert!(folder.is_ok() && main.is_ok() && lib.is_ok());
+    let (Ok(folder), Ok(main), Ok(lib)) = (folder, main, lib) else {
+        return;
+    };
+    let backend = Backend::detached();
+    backend.add_workspace_folder(folder.clone());
+    backend.index_workspace_folders(vec![folder]).await;
+    let text = |uri: &Url| backend.with_document(uri, |doc| doc.source.text().to_owned());
+
+    // Indexed files are served without reading them again, files outside the workspace are not
+    assert_eq!(text(&main).as_deref(), Some("= On disk"));
+    assert_eq!(text(&lib), None);
+
+    // An open document wins over the disk, the files it imports are indexed with it
+    let doc = Document::new(
+        &main,
+        "#import \"../outside/lib.typ\": helper\n#helper".to_owned(),
+        1,
+    );
+    assert!(backend.populate_symbol_table(&main, &doc).is_ok());
+    backend.documents.insert(main.clone(), doc);
+    backend.index_dependencies(&main).await;
+    assert_eq!(
+        text(&main).as_deref(),
+        Some("#import \"../outside/lib.typ\": helper\n#helper")
+    );
+    assert_eq!(text(&lib).as_deref(), Some("#let helper = 1"));
+    assert_eq!(
+        backend
+            .definition_at(&main, Position::new(1, 2))
+            .map(|definition| definition.location.uri),
+        Some(lib)
+    );
+    let _ = std::fs::remove_dir_all(root);
+}
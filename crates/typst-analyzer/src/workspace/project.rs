@@ -0,0 +1,348 @@
+//! Projects of the workspace.
+//!
+//! Files a document refers to, like images or the bibliography, are looked up relative to the
+//! root of its project, never relative to the directory the server was started in. The root is
+//! resolved per document, so every folder of a multi-root workspace is a project of its own.
+//!
+//! A project is compiled from a main file, which `#include`s the other files of the document. The
+//! main file is, in order of precedence, the file pinned with the `typst-analyzer.pinMain`
+//! command, the `main-file` setting, or a file that is not included by any other file. Analyses
+//! that span the whole document, like resolving labels or the bibliography, ask for the files of
+//! the documents a file is part of with [`Backend::document_files`].
+
+use std::collections::{HashSet, VecDeque};
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{anyhow, Error};
+use serde_json::Value;
+use tower_lsp::lsp_types::Url;
+use typst_analyzer_analysis::find_project_root;
+
+use crate::backend::Backend;
+use crate::workspace::symbols::DependencyKind;
+
+/// Pins the main file given as argument, or unpins it if the argument is missing or `null`.
+pub const PIN_MAIN_COMMAND: &str = "typst-analyzer.pinMain";
+
+impl Backend {
+    /// The workspace folder containing a file, the innermost one if folders are nested.
+    pub(crate) fn workspace_folder_of(&self, path: &Path) -> Option<PathBuf> {
+        self.workspace_folders()
+            .iter()
+            .filter_map(|folder| folder.to_file_path().ok())
+            .filter(|folder| path.starts_with(folder))
+            .max_by_key(|folder| folder.components().count())
+    }
+
+    /// The root of the project a document belongs to.
+    ///
+    /// This is the closest directory with a `typst.toml` manifest or a `typst-analyzer.toml`
+    /// config, without leaving the workspace folder of the document. Otherwise it is the
+    /// workspace folder, or the directory of the document if it lies outside the workspace.
+    /// Documents that are not files belong to the first workspace folder.
+    pub fn project_root(&self, uri: &Url) -> Option<PathBuf> {
+        let Ok(path) = uri.to_file_path() else {
+            return self
+                .workspace_folders()
+                .iter()
+                .find_map(|folder| folder.to_file_path().ok());
+        };
+        let dir = path.parent()?;
+        let folder = self.workspace_folder_of(dir);
+        find_project_root(dir, folder.as_deref())
+            .or(folder)
+            .or_else(|| Some(dir.to_path_buf()))
+    }
+
+    /// Resolves a path from the settings against the project root of a document, or against the
+    /// first workspace folder if there is no document.
+    ///
+    /// Relative paths are not resolved without a root, the directory the server was started in
+    /// means nothing to the project.
+    pub(crate) fn resolve_in_project(&self, uri: Option<&Url>, path: PathBuf) -> Option<PathBuf> {
+        if path.is_absolute() {
+            return Some(path);
+        }
+        let root = match uri {
+            Some(uri) => self.project_root(uri),
+            None => self
+                .workspace_folders()
+                .iter()
+                .find_map(|folder| folder.to_file_path().ok()),
+        };
+        root.map(|root| root.join(path))
+    }
+
+    /// Resolves a path written in a document, like the one of an `#include`.
+    ///
+    /// Paths starting with `/` are relative to the project root, others to the directory of the
+    /// document. Package imports like `@preview/cetz:0.3.0` do not point into the workspace.
+    pub(crate) fn resolve_source_path(&self, uri: &Url, path: &str) -> Option<Url> {
+        if path.starts_with('@') {
+            return None;
+        }
+        let resolved = match path.strip_prefix('/') {
+            Some(rooted) => self.project_root(uri)?.join(rooted),
+            None => uri.to_file_path().ok()?.parent()?.join(path),
+        };
+        Url::from_file_path(normalize(&resolved)).ok()
+    }
+
+    /// The main files of the documents a file is part of.
+    ///
+    /// A file included from several main files, like a shared chapter, is part of several
+    /// documents. A file that is not included anywhere is its own main file.
+    pub fn entry_points(&self, uri: &Url) -> Vec<Url> {
+        let configured = self
+            .settings_for(uri)
+            .main_file
+            .and_then(|path| self.resolve_in_project(Some(uri), path))
+            .and_then(|path| Url::from_file_path(path).ok());
+        let pinned = match self.pinned_main.read() {
+            Ok(pinned) => pinned.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        };
+        for main in [pinned, configured].into_iter().flatten() {
+            if self.included_files(&main).contains(uri) {
+                return vec![main];
+            }
+        }
+
+        // Walk the include graph backwards, files no one includes are main files
+        let mut roots = Vec::new();
+        let mut visited = HashSet::from([uri.clone()]);
+        let mut queue = VecDeque::from([uri.clone()]);
+        while let Some(file) = queue.pop_front() {
+            let includers = self.symbol_index.dependents(&file, DependencyKind::Include);
+            if includers.is_empty() {
+                roots.push(file);
+            }
+            for includer in includers {
+                if visited.insert(includer.clone()) {
+                    queue.push_back(includer);
+                }
+            }
+        }
+        if roots.is_empty() {
+            // Every file of an include cycle is included by another one
+            return vec![uri.clone()];
+        }
+        // A file that sets up the document is the more likely main file
+        roots.sort_by_key(|root| (!self.symbol_index.sets_document(root), root.to_string()));
+        roots
+    }
+
+    /// A main file and every file it includes, directly or through other files.
+    pub fn included_files(&self, main: &Url) -> Vec<Url> {
+        let mut files = vec![main.clone()];
+        let mut visited = HashSet::from([main.clone()]);
+        let mut next = 0;
+        while let Some(file) = files.get(next).cloned() {
+            next += 1;
+            for included in self
+                .symbol_index
+                .dependencies(&file, DependencyKind::Include)
+            {
+                if visited.insert(included.clone()) {
+                    files.push(included);
+                }
+            }
+        }
+        files
+    }
+
+    /// Every file of every document a file is part of, the file itself included.
+    pub fn document_files(&self, uri: &Url) -> Vec<Url> {
+        let mut files = Vec::new();
+        for main in self.entry_points(uri) {
+            for file in self.included_files(&main) {
+                if !files.contains(&file) {
+                    files.push(file);
+                }
+            }
+        }
+        files
+    }
+
+    /// Handles the [`PIN_MAIN_COMMAND`].
+    pub(crate) fn pin_main(&self, arguments: &[Value]) -> Result<(), Error> {
+        let main = match arguments.first() {
+            None | Some(Value::Null) => None,
+            Some(Value::String(uri)) => Some(Url::parse(uri)?),
+            Some(other) => return Err(anyhow!("expected a document uri, got {}", other)),
+        };
+        match self.pinned_main.write() {
+            Ok(mut pinned) => *pinned = main,
+            Err(poisoned) => *poisoned.into_inner() = main,
+        }
+        Ok(())
+    }
+}
+
+/// Removes `.` and `..` from a path without touching the file system.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+#[test]
+fn project_root_test() {
+    use crate::workspace::fs::temp_workspace;
+
+    let root = temp_workspace(
+        "project-root",
+        &[
+            ("workspace/package/typst.toml", ""),
+            ("workspace/package/src/lib.typ", ""),
+            ("workspace/loose/notes.typ", ""),
+            ("elsewhere/draft.typ", ""),
+        ],
+    );
+    let workspace = root.join("workspace");
+    let url = |path: &str| Url::from_file_path(root.join(path)).ok();
+    let backend = Backend::detached();
+    let untitled = Url::parse("untitled:Untitled-1");
+    assert!(untitled.is_ok());
+    let Ok(untitled) = untitled else { return };
+
+    // Without a workspace folder a document that is no file has no root to resolve against
+    assert_eq!(backend.project_root(&untitled), None);
+    assert_eq!(
+        backend.resolve_in_project(Some(&untitled), PathBuf::from("refs.bib")),
+        None
+    );
+    assert_eq!(
+        backend.resolve_in_project(None, root.join("refs.bib")),
+        Some(root.join("refs.bib"))
+    );
+
+    let folder = Url::from_file_path(&workspace);
+    assert!(folder.is_ok());
+    let Ok(folder) = folder else { return };
+    backend.add_workspace_folder(folder);
+    let root_of = |path: &str| url(path).and_then(|uri| backend.project_root(&uri));
+    assert_eq!(
+        root_of("workspace/package/src/lib.typ"),
+        Some(workspace.join("package"))
+    );
+    assert_eq!(
+        root_of("workspace/loose/notes.typ"),
+        Some(workspace.clone())
+    );
+    assert_eq!(root_of("elsewhere/draft.typ"), Some(root.join("elsewhere")));
+    assert_eq!(backend.project_root(&untitled), Some(workspace.clone()));
+    assert_eq!(
+        backend.resolve_in_project(Some(&untitled), PathBuf::from("refs.bib")),
+        Some(workspace.join("refs.bib"))
+    );
+
+    // The first folder stays the first one, whatever folders are added after it
+    for name in ["b", "c", "a"] {
+        let other = Url::from_directory_path(root.join(name));
+        assert!(other.is_ok());
+        let Ok(other) = other else { return };
+        backend.add_workspace_folder(other);
+    }
+    assert_eq!(backend.project_root(&untitled), Some(workspace.clone()));
+    assert_eq!(backend.workspace_folders().len(), 4);
+    let _ = std::fs::remove_dir_all(root);
+}
+
+#[tokio::test]
+async fn include_graph_test() {
+    use crate::workspace::fs::temp_workspace;
+
+    let root = temp_workspace(
+        "include-graph",
+        &[
+            (
+                "main.typ",
+                "#set document(title: \"Thesis\")\n#include \"left.typ\"\n#include \"right.typ\"",
+            ),
+            ("left.typ", "#include \"shared/leaf.typ\""),
+            ("right.typ", "#include \"./shared/../shared/leaf.typ\""),
+            ("appendix.typ", "#include \"/shared/leaf.typ\""),
+            ("shared/leaf.typ", ""),
+            ("cycle/a.typ", "#include \"b.typ\""),
+            ("cycle/b.typ", "#include \"a.typ\""),
+        ],
+    );
+    let folder = Url::from_directory_path(&root);
+    assert!(folder.is_ok());
+    let Ok(folder) = folder else { return };
+    let urls: Vec<Url> = [
+        "main.typ",
+        "left.typ",
+        "right.typ",
+        "appendix.typ",
+        "shared/leaf.typ",
+        "cycle/a.typ",
+        "cycle/b.typ",
+    ]
+    .iter()
+    .filter_map(|path| Url::from_file_path(root.join(path)).ok())
+    .collect();
+    let [main, left, right, appendix, leaf, a, b] = urls.as_slice() else {
+        return;
+    };
+    let backend = Backend::detached();
+    backend.add_workspace_folder(folder.clone());
+    backend.index_workspace_folders(vec![folder]).await;
+
+    // `./`, `..` and `/` paths all name the same file
+    for file in [left, right, appendix] {
+        assert_eq!(
+            backend
+                .symbol_index
+                .dependencies(file, DependencyKind::Include),
+            std::slice::from_ref(leaf)
+        );
+    }
+    assert_eq!(
+        backend
+            .symbol_index
+            .dependents(leaf, DependencyKind::Include),
+        [appendix.clone(), left.clone(), right.clone()]
+    );
+
+    // A diamond reaches its main file once, the file setting up the document comes first
+    assert_eq!(backend.entry_points(leaf), [main.clone(), appendix.clone()]);
+    assert_eq!(
+        backend.document_files(leaf),
+        [
+            main.clone(),
+            left.clone(),
+            right.clone(),
+            leaf.clone(),
+            appendix.clone()
+        ]
+    );
+    assert_eq!(backend.entry_points(main), std::slice::from_ref(main));
+
+    // Files of a cycle are their own main files
+    assert_eq!(backend.entry_points(a), std::slice::from_ref(a));
+    assert_eq!(backend.document_files(a), [a.clone(), b.clone()]);
+
+    // A pinned main file wins over the graph for the files it includes, and only for those
+    assert!(backend
+        .pin_main(&[Value::String(appendix.to_string())])
+        .is_ok());
+    assert_eq!(backend.entry_points(leaf), std::slice::from_ref(appendix));
+    assert_eq!(
+        backend.document_files(leaf),
+        [appendix.clone(), leaf.clone()]
+    );
+    assert_eq!(backend.entry_points(left), std::slice::from_ref(main));
+    assert!(backend.pin_main(&[Value::Null]).is_ok());
+    assert_eq!(backend.entry_points(leaf), [main.clone(), appendix.clone()]);
+    let _ = std::fs::remove_dir_all(root);
+}
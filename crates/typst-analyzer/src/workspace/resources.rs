@@ -0,0 +1,86 @@
+//! Files of the workspace that are not Typst sources.
+//!
+//! Bibliographies, images and fonts are read for completions, code actions and diagnostics. What
+//! is found in them is kept until the client reports that files of their kind changed, so a
+//! completion does not walk the project or load the system fonts again.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use typst_analyzer_analysis::bibliography::get_bib_keys;
+use typst_analyzer_analysis::completion::fonts::{get_fonts, FaceInfo};
+use typst_analyzer_analysis::get_images;
+
+use crate::backend::Backend;
+use crate::error_ctx::TypError;
+use crate::typ_logger;
+
+/// What was found in the bibliographies, images and fonts of the workspace.
+#[derive(Debug, Default)]
+pub struct ResourceCache {
+    /// The entry keys of a bibliography file, by path.
+    bib_keys: DashMap<PathBuf, Arc<Vec<String>>>,
+    /// The images below a project root, relative to it, by root.
+    images: DashMap<PathBuf, Arc<Vec<PathBuf>>>,
+    /// The system fonts and the fonts in a list of font paths, by the font paths.
+    fonts: DashMap<Vec<PathBuf>, Arc<Vec<FaceInfo>>>,
+}
+
+impl ResourceCache {
+    /// Forgets the keys of a bibliography file that changed.
+    pub fn clear_bibliography(&self, path: &Path) {
+        self.bib_keys.remove(path);
+    }
+
+    /// Forgets the images of every project, an image may be below several roots.
+    pub fn clear_images(&self) {
+        self.images.clear();
+    }
+
+    /// Forgets the fonts of every list of font paths.
+    pub fn clear_fonts(&self) {
+        self.fonts.clear();
+    }
+}
+
+impl Backend {
+    /// The entry keys of a bibliography file, empty if it cannot be read or parsed.
+    pub(crate) fn bib_keys(&self, path: &Path) -> Arc<Vec<String>> {
+        if let Some(keys) = self.resources.bib_keys.get(path) {
+            return keys.clone();
+        }
+        let keys = Arc::new(get_bib_keys(path).unwrap_or_else(|err| {
+            typ_logger!("{}", TypError::NonCriticalError(err.to_string().as_str()));
+            Vec::new()
+        }));
+        self.resources
+            .bib_keys
+            .insert(path.to_owned(), keys.clone());
+        keys
+    }
+
+    /// The images below a project root, relative to it.
+    pub(crate) fn project_images(&self, root: &Path) -> Arc<Vec<PathBuf>> {
+        if let Some(images) = self.resources.images.get(root) {
+            return images.clone();
+        }
+        let images = Arc::new(get_images(root).unwrap_or_default());
+        self.resources
+            .images
+            .insert(root.to_owned(), images.clone());
+        images
+    }
+
+    /// The system fonts and the fonts in `font_paths`.
+    pub(crate) fn fonts(&self, font_paths: &[PathBuf]) -> Arc<Vec<FaceInfo>> {
+        if let Some(fonts) = self.resources.fonts.get(font_paths) {
+            return fonts.clone();
+        }
+        let fonts = Arc::new(get_fonts(font_paths).ok().flatten().unwrap_or_default());
+        self.resources
+            .fonts
+            .insert(font_paths.to_vec(), fonts.clone());
+        fonts
+    }
+}
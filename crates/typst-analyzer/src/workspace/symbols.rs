@@ -1 +1,387 @@
+//! The symbol index of the workspace.
+//!
+//! Symbols are stored per file and per [`SymbolKind`], with definitions and references kept
+//! apart, so a label and a reference with the same name, or the same label name in two files, no
+//! longer overwrite each other. The entries of a file are replaced as a whole whenever the file is
+//! reanalysed.
+//!
+//! Besides symbols, the index records which files a file includes, imports or reads its
+//! bibliography from. These edges make up the include graph of a project. They are also kept
+//! reversed, so finding the files that refer to a file does not look at every file.
 
+use std::collections::{BTreeSet, HashMap};
+
+use dashmap::{DashMap, Entry};
+use tower_lsp::lsp_types::{Location, Url};
+
+use crate::document::Document;
+use crate::symbols::Symbol;
+
+/// What a symbol names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SymbolKind {
+    /// A `<label>`, referenced with `@label`.
+    Label,
+    /// A heading, named by its text.
+    Heading,
+    /// A function bound at the top level of a file, which other files can import.
+    Function,
+    /// Any other binding at the top level of a file.
+    Variable,
+}
+
+/// Whether a symbol introduces a name or uses it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SymbolRole {
+    Definition,
+    Reference,
+}
+
+/// How a file refers to another one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DependencyKind {
+    /// `#include "chapter.typ"`, the content becomes part of the including document.
+    Include,
+    /// `#import "template.typ": conf`, only bindings are taken over.
+    Import,
+    /// `#bibliography("refs.yml")`.
+    Bibliography,
+}
+
+/// A file referred to by its path in another file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dependency {
+    pub kind: DependencyKind,
+    pub target: Url,
+    /// Where the path is written.
+    pub location: Location,
+}
+
+/// The symbols of one kind found in a file, by name.
+#[derive(Debug, Clone, Default)]
+pub struct SymbolEntries {
+    pub definitions: HashMap<String, Vec<Symbol>>,
+    pub references: HashMap<String, Vec<Symbol>>,
+}
+
+impl SymbolEntries {
+    fn by_role(&self, role: SymbolRole) -> &HashMap<String, Vec<Symbol>> {
+        match role {
+            SymbolRole::Definition => &self.definitions,
+            SymbolRole::Reference => &self.references,
+        }
+    }
+
+    fn by_role_mut(&mut self, role: SymbolRole) -> &mut HashMap<String, Vec<Symbol>> {
+        match role {
+            SymbolRole::Definition => &mut self.definitions,
+            SymbolRole::Reference => &mut self.references,
+        }
+    }
+}
+
+/// All symbols found in a file.
+#[derive(Debug, Clone, Default)]
+pub struct FileSymbols {
+    kinds: HashMap<SymbolKind, SymbolEntries>,
+    dependencies: Vec<Dependency>,
+    /// Whether the file contains `#set document(..)`, which only makes sense in a main file.
+    pub sets_document: bool,
+    /// The file as read from disk, so requests on a file that is not open do not read it again.
+    /// Open documents leave it out, the editor has their content.
+    pub document: Option<Document>,
+}
+
+impl FileSymbols {
+    pub fn insert(&mut self, kind: SymbolKind, role: SymbolRole, symbol: Symbol) {
+        self.kinds
+            .entry(kind)
+            .or_default()
+            .by_role_mut(role)
+            .entry(symbol.name.clone())
+            .or_default()
+            .push(symbol);
+    }
+
+    /// The symbols with the given kind, role and name, in document order.
+    pub fn get(&self, kind: SymbolKind, role: SymbolRole, name: &str) -> &[Symbol] {
+        self.kinds
+            .get(&kind)
+            .and_then(|entries| entries.by_role(role).get(name))
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    pub fn add_dependency(&mut self, dependency: Dependency) {
+        self.dependencies.push(dependency);
+    }
+
+    /// The files referred to in the given way, in document order.
+    pub fn dependencies(&self, kind: DependencyKind) -> impl Iterator<Item = &Dependency> {
+        self.dependencies
+            .iter()
+            .filter(move |dependency| dependency.kind == kind)
+    }
+
+    /// Every symbol with the given kind and role, in document order.
+    pub fn all(&self, kind: SymbolKind, role: SymbolRole) -> Vec<&Symbol> {
+        let mut symbols: Vec<&Symbol> = self
+            .kinds
+            .get(&kind)
+            .into_iter()
+            .flat_map(|entries| entries.by_role(role).values().flatten())
+            .collect();
+        symbols.sort_by_key(|symbol| position_key(&symbol.location));
+        symbols
+    }
+}
+
+/// The symbols of every analysed file, keyed by file URI.
+#[derive(Debug, Default)]
+pub struct SymbolIndex {
+    files: DashMap<Url, FileSymbols>,
+    /// The files referring to a file in a given way, the dependencies of `files` reversed.
+    dependents: DashMap<(Url, DependencyKind), BTreeSet<Url>>,
+}
+
+impl SymbolIndex {
+    /// Replaces the symbols of a file.
+    pub fn update(&self, uri: Url, symbols: FileSymbols) {
+        // The file stays locked until its edges are replaced, updates of it do not interleave
+        let mut file = self.files.entry(uri.clone()).or_default();
+        let previous = std::mem::replace(&mut *file, symbols);
+        self.unlink(&uri, &previous);
+        self.link(&uri, &file);
+    }
+
+    /// Forgets a file.
+    pub fn remove(&self, uri: &Url) {
+        if let Entry::Occupied(file) = self.files.entry(uri.clone()) {
+            self.unlink(uri, file.get());
+            file.remove();
+        }
+    }
+
+    fn link(&self, uri: &Url, symbols: &FileSymbols) {
+        for dependency in &symbols.dependencies {
+            self.dependents
+                .entry((dependency.target.clone(), dependency.kind))
+                .or_default()
+                .insert(uri.clone());
+        }
+    }
+
+    fn unlink(&self, uri: &Url, symbols: &FileSymbols) {
+        for dependency in &symbols.dependencies {
+            let key = (dependency.target.clone(), dependency.kind);
+            if let Entry::Occupied(mut dependents) = self.dependents.entry(key) {
+                dependents.get_mut().remove(uri);
+                if dependents.get().is_empty() {
+                    dependents.remove();
+                }
+            }
+        }
+    }
+
+    /// Keeps only the files for which `keep` returns true.
+    ///
+    /// `keep` runs without any lock on the index held, so it may look at other state.
+    pub fn retain(&self, keep: impl Fn(&Url) -> bool) {
+        let files: Vec<Url> = self.files.iter().map(|entry| entry.key().clone()).collect();
+        for uri in files.into_iter().filter(|uri| !keep(uri)) {
+            self.remove(&uri);
+        }
+    }
+
+    /// Runs a query on the symbols of a file, if it has been analysed.
+    ///
+    /// The file is locked while `query` runs, it must not look up other files in the index.
+    pub fn with_file<R>(&self, uri: &Url, query: impl FnOnce(&FileSymbols) -> R) -> Option<R> {
+        self.files.get(uri).map(|symbols| query(&symbols))
+    }
+
+    /// The files a file refers to in the given way.
+    pub fn dependencies(&self, uri: &Url, kind: DependencyKind) -> Vec<Url> {
+        self.files
+            .get(uri)
+            .map(|symbols| {
+                symbols
+                    .dependencies(kind)
+                    .map(|dependency| dependency.target.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// The files referring to `target` in the given way, ordered by URI.
+    pub fn dependents(&self, target: &Url, kind: DependencyKind) -> Vec<Url> {
+        self.dependents
+            .get(&(target.clone(), kind))
+            .map(|dependents| dependents.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// The symbols with the given kind and role in every file, by file and position.
+    pub fn all(&self, kind: SymbolKind, role: SymbolRole) -> Vec<Symbol> {
+        let mut symbols: Vec<Symbol> = self
+            .files
+            .iter()
+            .flat_map(|entry| {
+                entry
+                    .all(kind, role)
+                    .into_iter()
+                    .cloned()
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        symbols.sort_by(|a, b| {
+            (a.location.uri.as_str(), position_key(&a.location))
+                .cmp(&(b.location.uri.as_str(), position_key(&b.location)))
+        });
+        symbols
+    }
+
+    /// The content of a file that is not open, as it was read from disk. The copy shares its text
+    /// and syntax tree with the index.
+    pub fn disk_document(&self, uri: &Url) -> Option<Document> {
+        self.files
+            .get(uri)
+            .and_then(|symbols| symbols.document.clone())
+    }
+
+    pub fn sets_document(&self, uri: &Url) -> bool {
+        self.files
+            .get(uri)
+            .is_some_and(|symbols| symbols.sets_document)
+    }
+}
+
+/// Orders locations in a file by where they start.
+fn position_key(location: &Location) -> (u32, u32) {
+    (location.range.start.line, location.range.start.character)
+}
+
+#[test]
+fn symbol_index_test() {
+    use tower_lsp::lsp_types::{Position, Range};
+    use typst_syntax::SyntaxKind;
+
+    let (main, chapter) = (
+        Url::parse("file:///project/main.typ"),
+        Url::parse("file:///project/chapter.typ"),
+    );
+    assert!(main.is_ok() && chapter.is_ok());
+    let (Ok(main), Ok(chapter)) = (main, chapter) else {
+        return;
+    };
+    let symbol = |uri: &Url, name: &str, line: u32| Symbol {
+        name: name.to_owned(),
+        location: Location::new(
+            uri.clone(),
+            Range::new(Position::new(line, 0), Position::new(line, 4)),
+        ),
+        symbol_type: SyntaxKind::Label,
+        file: crate::document::file_id(uri),
+    };
+    let names = |symbols: Vec<&Symbol>| -> Vec<String> {
+        symbols
+            .into_iter()
+            .map(|symbol| symbol.name.clone())
+            .collect()
+    };
+
+    let mut file = FileSymbols::default();
+    for (name, line) in [("zeta", 0), ("alpha", 1), ("mid", 2), ("alpha", 3)] {
+        file.insert(
+            SymbolKind::Label,
+            SymbolRole::Definition,
+            symbol(&main, name, line),
+        );
+    }
+    file.insert(
+        SymbolKind::Label,
+        SymbolRole::Reference,
+        symbol(&main, "zeta", 5),
+    );
+    // Whatever the names hash to, symbols come back in document order
+    assert_eq!(
+        names(file.all(SymbolKind::Label, SymbolRole::Definition)),
+        ["zeta", "alpha", "mid", "alpha"]
+    );
+    assert_eq!(
+        names(file.all(SymbolKind::Label, SymbolRole::Reference)),
+        ["zeta"]
+    );
+    assert_eq!(
+        file.get(SymbolKind::Label, SymbolRole::Definition, "alpha")
+            .len(),
+        2
+    );
+    assert!(file
+        .all(SymbolKind::Heading, SymbolRole::Definition)
+        .is_empty());
+
+    let index = SymbolIndex::default();
+    index.update(main.clone(), file);
+    let mut other = FileSymbols::default();
+    other.insert(
+        SymbolKind::Label,
+        SymbolRole::Definition,
+        symbol(&chapter, "intro", 0),
+    );
+    index.update(chapter.clone(), other);
+    assert_eq!(
+        index.with_file(&main, |file| file
+            .get(SymbolKind::Label, SymbolRole::Reference, "zeta")
+            .len()),
+        Some(1)
+    );
+    // Files in the order of their URIs, `chapter.typ` before `main.typ`
+    let all: Vec<String> = index
+        .all(SymbolKind::Label, SymbolRole::Definition)
+        .into_iter()
+        .map(|symbol| symbol.name)
+        .collect();
+    assert_eq!(all, ["intro", "zeta", "alpha", "mid", "alpha"]);
+
+    // Reanalysing a file replaces its symbols as a whole
+    index.update(main.clone(), FileSymbols::default());
+    assert_eq!(
+        index.with_file(&main, |file| file
+            .all(SymbolKind::Label, SymbolRole::Definition)
+            .len()),
+        Some(0)
+    );
+    index.remove(&chapter);
+    assert!(index.with_file(&chapter, |_| ()).is_none());
+
+    // Edges follow the files they are written in
+    let include = |target: &Url| Dependency {
+        kind: DependencyKind::Include,
+        target: target.clone(),
+        location: symbol(&main, "", 0).location,
+    };
+    let mut file = FileSymbols::default();
+    file.add_dependency(include(&chapter));
+    file.add_dependency(include(&chapter));
+    index.update(main.clone(), file);
+    assert_eq!(
+        index.dependents(&chapter, DependencyKind::Include),
+        std::slice::from_ref(&main)
+    );
+    assert!(index
+        .dependents(&chapter, DependencyKind::Import)
+        .is_empty());
+    index.update(main.clone(), FileSymbols::default());
+    assert!(index
+        .dependents(&chapter, DependencyKind::Include)
+        .is_empty());
+    let mut file = FileSymbols::default();
+    file.add_dependency(include(&chapter));
+    index.update(main.clone(), file);
+    index.remove(&main);
+    assert!(index
+        .dependents(&chapter, DependencyKind::Include)
+        .is_empty());
+    assert!(index.dependents.is_empty());
+}
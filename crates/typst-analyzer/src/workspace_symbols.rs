@@ -0,0 +1,373 @@
+//! Searching the symbols of every Typst file in the workspace.
+//!
+//! Headings, labels and the functions and variables a file binds at its top level are taken from
+//! the symbol index, so files that are not open are found too. The query is matched fuzzily, its
+//! characters have to appear in the name in the same order, and the best matches come first.
+//!
+//! Symbols of files that are not open are sent with only their file when the client can resolve
+//! them, their range is looked up once the client asks for it with `workspaceSymbol/resolve`.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use serde_json::Value;
+use tower::Service;
+use tower_lsp::jsonrpc::{Request, Response};
+use tower_lsp::lsp_types::request::{Request as _, WorkspaceSymbolRequest};
+use tower_lsp::lsp_types::{
+    Location, OneOf, WorkspaceLocation, WorkspaceSymbol, WorkspaceSymbolParams,
+    WorkspaceSymbolResponse,
+};
+use tower_lsp::{jsonrpc, ExitedError, LspService};
+use typst_analyzer_analysis::outline::OutlineKind;
+
+use crate::backend::Backend;
+use crate::document_symbols::symbol_kind;
+use crate::prelude::*;
+use crate::workspace::symbols::{SymbolKind, SymbolRole};
+
+/// The kinds of symbols searched, with the outline entries they are shown like.
+const SEARCHED: [(SymbolKind, OutlineKind); 4] = [
+    (SymbolKind::Heading, OutlineKind::Heading(1)),
+    (SymbolKind::Label, OutlineKind::Label),
+    (SymbolKind::Function, OutlineKind::Function),
+    (SymbolKind::Variable, OutlineKind::Variable),
+];
+
+/// Large workspaces have thousands of headings, clients only show the first few matches anyway.
+const MAX_RESULTS: usize = 256;
+
+pub(crate) trait HandleWorkspaceSymbols {
+    fn provide_workspace_symbols(
+        &self,
+        params: WorkspaceSymbolParams,
+    ) -> Result<Vec<WorkspaceSymbol>, Error>;
+
+    fn resolve_workspace_symbol(&self, symbol: WorkspaceSymbol) -> Result<WorkspaceSymbol, Error>;
+}
+
+impl HandleWorkspaceSymbols for Backend {
+    fn provide_workspace_symbols(
+        &self,
+        params: WorkspaceSymbolParams,
+    ) -> Result<Vec<WorkspaceSymbol>, Error> {
+        let mut matches = Vec::new();
+        for (kind, outline_kind) in SEARCHED {
+            for symbol in self.symbol_index.all(kind, SymbolRole::Definition) {
+                if let Some(score) = fuzzy_score(&params.query, &symbol.name) {
+                    matches.push((score, symbol, kind, outline_kind));
+                }
+            }
+        }
+        matches.sort_by(|(a_score, a, ..), (b_score, b, ..)| {
+            b_score
+                .cmp(a_score)
+                .then_with(|| a.name.len().cmp(&b.name.len()))
+                .then_with(|| a.name.cmp(&b.name))
+                .then_with(|| a.location.uri.as_str().cmp(b.location.uri.as_str()))
+        });
+        matches.truncate(MAX_RESULTS);
+
+        let resolve_support = self.supports_symbol_resolve();
+        let symbols = matches
+            .into_iter()
+            .map(|(_, symbol, kind, outline_kind)| {
+                let container_name = symbol
+                    .location
+                    .uri
+                    .path_segments()
+                    .and_then(|mut segments| segments.next_back())
+                    .map(str::to_owned);
+                // Which of the symbols with this name in the file it is, to find it again
+                let nth = self
+                    .symbol_index
+                    .with_file(&symbol.location.uri, |file_symbols| {
+                        file_symbols
+                            .get(kind, SymbolRole::Definition, &symbol.name)
+                            .iter()
+                            .position(|other| other.location == symbol.location)
+                    })
+                    .flatten()
+                    .unwrap_or_default();
+                let lazy = resolve_support && !self.documents.contains_key(&symbol.location.uri);
+                let location = if lazy {
+                    OneOf::Right(WorkspaceLocation {
+                        uri: symbol.location.uri,
+                    })
+                } else {
+                    OneOf::Left(symbol.location)
+                };
+                WorkspaceSymbol {
+                    name: symbol.name,
+                    kind: symbol_kind(outline_kind),
+                    tags: None,
+                    container_name,
+                    location,
+                    data: Some(Value::from(nth)),
+                }
+            })
+            .collect();
+        Ok(symbols)
+    }
+
+    // A symbol handed out with only the file it is in gets its range here, from the index.
+    fn resolve_workspace_symbol(&self, symbol: WorkspaceSymbol) -> Result<WorkspaceSymbol, Error> {
+        let uri = match &symbol.location {
+            OneOf::Left(_) => return Ok(symbol),
+            OneOf::Right(location) => location.uri.clone(),
+        };
+        let nth = symbol.data.as_ref().and_then(Value::as_u64).unwrap_or(0) as usize;
+        let location: Location = self
+            .symbol_index
+            .with_file(&uri, |file_symbols| {
+                SEARCHED
+                    .iter()
+                    .filter(|(_, outline_kind)| symbol_kind(*outline_kind) == symbol.kind)
+                    .find_map(|(kind, _)| {
+                        let found = file_symbols.get(*kind, SymbolRole::Definition, &symbol.name);
+                        // The file may have lost symbols since the search, fall back to the first
+                        found.get(nth).or(found.first())
+                    })
+                    .map(|found| found.location.clone())
+            })
+            .flatten()
+            .ok_or(anyhow!("symbol {} not found in {}", symbol.name, uri))?;
+        Ok(WorkspaceSymbol {
+            location: OneOf::Left(location),
+            ..symbol
+        })
+    }
+}
+
+impl Backend {
+    /// Whether the client asks for the range of a workspace symbol when it needs it.
+    fn supports_symbol_resolve(&self) -> bool {
+        self.client_capabilities()
+            .workspace
+            .and_then(|workspace| workspace.symbol)
+            .and_then(|symbol| symbol.resolve_support)
+            .is_some_and(|support| support.properties.iter().any(|p| p == "location.range"))
+    }
+}
+
+/// The language server, answering `workspace/symbol` with [`WorkspaceSymbol`]s.
+///
+/// tower-lsp only lets `LanguageServer::symbol` return `SymbolInformation`, whose location needs
+/// a range, and it does not let a custom method replace it. Every other request is passed on to
+/// the [`LspService`].
+pub struct WorkspaceSymbolService {
+    inner: LspService<Backend>,
+}
+
+impl WorkspaceSymbolService {
+    pub fn new(inner: LspService<Backend>) -> Self {
+        Self { inner }
+    }
+}
+
+impl Service<Request> for WorkspaceSymbolService {
+    type Response = Option<Response>;
+    type Error = ExitedError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let backend = self.inner.inner().clone();
+        // Before initialize the server answers with the error the protocol asks for
+        let initialized = backend.client_capabilities.get().is_some();
+        if request.method() != WorkspaceSymbolRequest::METHOD || !initialized {
+            return Box::pin(self.inner.call(request));
+        }
+        Box::pin(async move {
+            let (_, id, params) = request.into_parts();
+            // A notification with the name of a request gets no answer
+            let Some(id) = id else {
+                return Ok(None);
+            };
+            let result = serde_json::from_value(params.unwrap_or_default())
+                .map_err(|err| jsonrpc::Error::invalid_params(err.to_string()))
+                .map(|params| {
+                    let symbols = backend
+                        .provide_workspace_symbols(params)
+                        .ok()
+                        .map(WorkspaceSymbolResponse::Nested);
+                    serde_json::to_value(symbols).unwrap_or_default()
+                });
+            Ok(Some(Response::from_parts(id, result)))
+        })
+    }
+}
+
+/// How well a query matches a name, `None` if it does not match at all.
+///
+/// Every character of the query has to appear in the name in the same order, ignoring case.
+/// Characters matched one after the other or at the start of a word score higher, and so do
+/// names starting with the query.
+fn fuzzy_score(query: &str, name: &str) -> Option<i64> {
+    let query: Vec<char> = query
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .flat_map(char::to_lowercase)
+        .collect();
+    // An empty query lists everything
+    if query.is_empty() {
+        return Some(0);
+    }
+    let name: Vec<char> = name.chars().collect();
+    let mut score = 0;
+    let mut next = 0;
+    let mut previous: Option<usize> = None;
+    for (i, c) in name.iter().enumerate() {
+        let Some(wanted) = query.get(next) else {
+            break;
+        };
+        if !c.to_lowercase().eq(std::iter::once(*wanted)) {
+            continue;
+        }
+        score += 1;
+        if previous.is_some_and(|previous| previous + 1 == i) {
+            score += 5;
+        }
+        let word_start = match i.checked_sub(1).and_then(|before| name.get(before)) {
+            None => true,
+            Some(before) => {
+                !before.is_alphanumeric() || (before.is_lowercase() && c.is_uppercase())
+            }
+        };
+        if word_start {
+            score += 8;
+        }
+        // Matches far into the name are worth less
+        if previous.is_none() {
+            score -= i.min(10) as i64;
+        }
+        previous = Some(i);
+        next += 1;
+    }
+    if next < query.len() {
+        return None;
+    }
+    let lowercase: Vec<char> = name.iter().flat_map(|c| c.to_lowercase()).collect();
+    if lowercase == query {
+        score += 100;
+    } else if lowercase.starts_with(&query) {
+        score += 50;
+    }
+    Some(score)
+}
+
+#[test]
+fn fuzzy_score_test() {
+    assert_eq!(fuzzy_score("xyz", "Introduction"), None);
+    assert_eq!(fuzzy_score("", "Introduction"), Some(0));
+    assert!(fuzzy_score("cf", "conf").is_some());
+    // Word starts beat matches in the middle of a word
+    assert!(fuzzy_score("lem", "Lemma 7") > fuzzy_score("lem", "Problem"));
+    assert!(fuzzy_score("mt", "main-theorem") > fuzzy_score("mt", "commitment"));
+    // An exact match beats a longer name starting with the query
+    assert!(fuzzy_score("intro", "intro") > fuzzy_score("intro", "Introduction"));
+    assert!(fuzzy_score("intro", "Introduction") > fuzzy_score("intro", "An intro"));
+}
+
+#[tokio::test]
+async fn workspace_symbol_resolve_test() {
+    use tower_lsp::lsp_types::{Position, Range, Url};
+
+    use crate::document::Document;
+    use crate::workspace::fs::temp_workspace;
+
+    let root = temp_workspace(
+        "workspace-symbols",
+        &[
+            ("main.typ", "= Lemmas\n#include \"chapter.typ\""),
+            ("chapter.typ", "= Lemma\nFirst.\n= Lemma\nSecond."),
+        ],
+    );
+    let (folder, main, chapter) = (
+        Url::from_directory_path(&root),
+        Url::from_file_path(root.join("main.typ")),
+        Url::from_file_path(root.join("chapter.typ")),
+    );
+    assert!(folder.is_ok() && main.is_ok() && chapter.is_ok());
+    let (Ok(folder), Ok(main), Ok(chapter)) = (folder, main, chapter) else {
+        return;
+    };
+    let (service, _socket) = LspService::new(Backend::new);
+    let backend = service.inner().clone();
+    let mut service = WorkspaceSymbolService::new(service);
+    let mut request = |request: Request| {
+        let response = service.call(request);
+        async move {
+            match response.await {
+                Ok(Some(response)) => response.into_parts().1.ok(),
+                _ => None,
+            }
+        }
+    };
+    let initialize = Request::build("initialize")
+        .id(1)
+        .params(serde_json::json!({
+            "capabilities": {
+                "workspace": { "symbol": { "resolveSupport": { "properties": ["location.range"] } } }
+            }
+        }))
+        .finish();
+    assert!(request(initialize).await.is_some());
+    backend.add_workspace_folder(folder.clone());
+    backend.index_workspace_folders(vec![folder]).await;
+    backend.documents.insert(
+        main.clone(),
+        Document::new(&main, "= Lemmas\n#include \"chapter.typ\"".to_owned(), 1),
+    );
+
+    let search = Request::build("workspace/symbol")
+        .id(2)
+        .params(serde_json::json!({ "query": "lemma" }))
+        .finish();
+    let symbols = request(search)
+        .await
+        .and_then(|value| serde_json::from_value::<Vec<WorkspaceSymbol>>(value).ok());
+    assert!(symbols.is_some());
+    let Some(symbols) = symbols else { return };
+    let locations: Vec<_> = symbols
+        .iter()
+        .map(|symbol| (symbol.name.as_str(), &symbol.location))
+        .collect();
+    // The open file comes with its range, the other one only with the file
+    let main_range = Range::new(Position::new(0, 0), Position::new(0, 8));
+    let lazy = OneOf::Right(WorkspaceLocation {
+        uri: chapter.clone(),
+    });
+    assert_eq!(
+        locations,
+        [
+            ("Lemma", &lazy),
+            ("Lemma", &lazy),
+            (
+                "Lemmas",
+                &OneOf::Left(Location::new(main.clone(), main_range))
+            ),
+        ]
+    );
+
+    // Resolving finds the second of two symbols with the same name
+    let resolve = Request::build("workspaceSymbol/resolve")
+        .id(3)
+        .params(serde_json::to_value(&symbols[1]).unwrap_or_default())
+        .finish();
+    let resolved = request(resolve)
+        .await
+        .and_then(|value| serde_json::from_value::<WorkspaceSymbol>(value).ok());
+    assert_eq!(
+        resolved.map(|symbol| symbol.location),
+        Some(OneOf::Left(Location::new(
+            chapter,
+            Range::new(Position::new(2, 0), Position::new(2, 7))
+        )))
+    );
+    let _ = std::fs::remove_dir_all(root);
+}